@@ -0,0 +1,217 @@
+//! Runs the Lox programs in `benches/lox` with each backend, and reports the time and the
+//! number of allocations they take:
+//!
+//! ```text
+//! cargo bench --bench interpreter [-- [options] [workload...]]
+//! ```
+//!
+//! Only the workloads whose names contain one of the given words are run. The options are
+//! - `--runs=N`: the number of times each workload is run. The fastest run is reported.
+//! - `--save=FILE`: saves the results, to be compared with a later run.
+//! - `--baseline=FILE`: shows how the results differ from saved ones.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use std::{env, fs, process};
+
+use rlox::{Backend, Lox};
+
+const BACKENDS: [Backend; 2] = [Backend::TreeWalker, Backend::Bytecode];
+
+/// Counts the allocations made by the program
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+static ALLOCATED_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED_BYTES.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED_BYTES.fetch_add(new_size.saturating_sub(layout.size()), Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+#[derive(Clone, Copy)]
+struct Measurement {
+    time: Duration,
+    allocations: usize,
+    bytes: usize,
+}
+
+struct Options {
+    runs: usize,
+    save: Option<PathBuf>,
+    baseline: Option<PathBuf>,
+    filters: Vec<String>,
+}
+
+fn main() {
+    let options = parse_options();
+    let baseline = options.baseline.as_deref().map(load).unwrap_or_default();
+    let mut results = BTreeMap::new();
+    println!(
+        "{:<10} {:<12} {:>10} {:>12} {:>12}  compared to",
+        "workload", "backend", "time", "allocations", "bytes"
+    );
+    for (name, path) in workloads(&options.filters) {
+        let source = fs::read_to_string(&path).expect("Failed to read the workload");
+        let mut outputs = Vec::new();
+        let mut tree_walker = None;
+        for backend in BACKENDS {
+            let (measurement, output) = measure(&path, &source, backend, options.runs);
+            outputs.push(output);
+            let mut comparisons = Vec::new();
+            if let Some(tree_walker) = tree_walker {
+                comparisons.push(format!("tree-walker {}", ratio(tree_walker, measurement)));
+            } else {
+                tree_walker = Some(measurement);
+            }
+            let key = format!("{name} {backend:?}");
+            if let Some(saved) = baseline.get(&key) {
+                comparisons.push(format!("baseline {}", ratio(*saved, measurement)));
+            }
+            println!(
+                "{name:<10} {:<12} {:>10.2?} {:>12} {:>12}  {}",
+                format!("{backend:?}"),
+                measurement.time,
+                measurement.allocations,
+                measurement.bytes,
+                comparisons.join(", ")
+            );
+            results.insert(key, measurement);
+        }
+        if outputs.windows(2).any(|pair| pair[0] != pair[1]) {
+            eprintln!("The backends printed different output for {name}");
+            process::exit(1);
+        }
+    }
+    if let Some(path) = &options.save {
+        save(path, &results);
+    }
+}
+
+fn parse_options() -> Options {
+    let mut options = Options {
+        runs: 3,
+        save: None,
+        baseline: None,
+        filters: Vec::new(),
+    };
+    for arg in env::args().skip(1) {
+        if let Some(runs) = arg.strip_prefix("--runs=") {
+            options.runs = runs.parse().expect("--runs must be a number");
+        } else if let Some(path) = arg.strip_prefix("--save=") {
+            options.save = Some(path.into());
+        } else if let Some(path) = arg.strip_prefix("--baseline=") {
+            options.baseline = Some(path.into());
+        } else if !arg.starts_with("--") {
+            options.filters.push(arg);
+        }
+        // Other options, such as the --bench passed by Cargo, are ignored
+    }
+    options
+}
+
+/// The names and paths of the workloads to run, sorted by name
+fn workloads(filters: &[String]) -> Vec<(String, PathBuf)> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("benches/lox");
+    let mut workloads: Vec<_> = fs::read_dir(&dir)
+        .expect("Failed to read the workloads")
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "lox"))
+        .map(|path| {
+            let name = path.file_stem().unwrap().to_string_lossy().into_owned();
+            (name, path)
+        })
+        .filter(|(name, _)| filters.is_empty() || filters.iter().any(|f| name.contains(f)))
+        .collect();
+    workloads.sort();
+    workloads
+}
+
+/// Runs the workload a number of times, and returns the fastest run and what it printed
+fn measure(path: &Path, source: &str, backend: Backend, runs: usize) -> (Measurement, Vec<u8>) {
+    let mut fastest: Option<Measurement> = None;
+    let mut output = Vec::new();
+    for _ in 0..runs.max(1) {
+        let mut lox = Lox::with_backend(Vec::new(), backend);
+        let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+        let bytes = ALLOCATED_BYTES.load(Ordering::Relaxed);
+        let start = Instant::now();
+        if let Err(error) = lox.eval_named(&path.display().to_string(), source) {
+            eprintln!("{} failed: {error}", path.display());
+            process::exit(1);
+        }
+        let measurement = Measurement {
+            time: start.elapsed(),
+            allocations: ALLOCATIONS.load(Ordering::Relaxed) - allocations,
+            bytes: ALLOCATED_BYTES.load(Ordering::Relaxed) - bytes,
+        };
+        if fastest.is_none_or(|fastest| measurement.time < fastest.time) {
+            fastest = Some(measurement);
+        }
+        output = lox.into_output();
+    }
+    (fastest.unwrap(), output)
+}
+
+/// Describes a measurement relative to an earlier one. Less than 1x is an improvement.
+fn ratio(before: Measurement, after: Measurement) -> String {
+    format!(
+        "{:.2}x time, {:.2}x allocations",
+        after.time.as_secs_f64() / before.time.as_secs_f64(),
+        after.allocations as f64 / before.allocations.max(1) as f64
+    )
+}
+
+/// Saves the results as lines of `<workload> <backend> <nanoseconds> <allocations> <bytes>`
+fn save(path: &Path, results: &BTreeMap<String, Measurement>) {
+    let lines: Vec<_> = results
+        .iter()
+        .map(|(key, m)| {
+            format!(
+                "{key} {} {} {}\n",
+                m.time.as_nanos(),
+                m.allocations,
+                m.bytes
+            )
+        })
+        .collect();
+    fs::write(path, lines.concat()).expect("Failed to save the results");
+}
+
+fn load(path: &Path) -> BTreeMap<String, Measurement> {
+    let contents = fs::read_to_string(path).expect("Failed to read the baseline");
+    contents
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<_> = line.split_whitespace().collect();
+            let [workload, backend, nanos, allocations, bytes] = fields[..] else {
+                return None;
+            };
+            let measurement = Measurement {
+                time: Duration::from_nanos(nanos.parse().ok()?),
+                allocations: allocations.parse().ok()?,
+                bytes: bytes.parse().ok()?,
+            };
+            Some((format!("{workload} {backend}"), measurement))
+        })
+        .collect()
+}
@@ -1,34 +1,33 @@
+use std::io::Write;
 use std::rc::Rc;
 use std::time::SystemTime;
 
-use crate::environment::Environment;
+use crate::list::ListFunction;
 use crate::literal_value::LiteralValue;
 use crate::lox_callable::LoxCallable;
-use crate::token::Token;
-use crate::token_type::TokenType;
+use crate::Lox;
+
+pub fn add_builtin_functions<W: Write>(lox: &mut Lox<W>) {
+    lox.register_fn("clock", clock);
+    for function in ListFunction::ALL {
+        lox.define_list_function(function);
+    }
+}
+
+/// Wraps a function on lists so that the tree-walker can call it
+pub fn list_function(function: ListFunction) -> LiteralValue {
+    LiteralValue::Function(LoxCallable::from_fn(
+        function.name(),
+        Rc::new(move |arguments, _env, _interpreter| function.call(&arguments)),
+        function.arity(),
+    ))
+}
 
 #[allow(clippy::cast_precision_loss)]
-pub fn add_builtin_functions(environment: &mut Environment) {
-    environment
-        .define(
-            &Token {
-                token_type: TokenType::Fun,
-                lexeme: "clock".to_string(),
-                line: 0,
-                count: 0,
-            },
-            LiteralValue::Function(LoxCallable::from_fn(
-                Rc::new(|_args, _env, _out| {
-                    Ok(LiteralValue::Number(
-                        (SystemTime::now()
-                            .duration_since(SystemTime::UNIX_EPOCH)
-                            .unwrap()
-                            .as_micros() as f64)
-                            / 1_000_000.0,
-                    ))
-                }),
-                0,
-            )),
-        )
-        .unwrap();
+fn clock() -> f64 {
+    (SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_micros() as f64)
+        / 1_000_000.0
 }
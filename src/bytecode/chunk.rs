@@ -0,0 +1,73 @@
+use crate::bytecode::value::Value;
+use crate::span::Span;
+
+/// The instructions of the virtual machine. Operands that refer to constants are indices into
+/// the constant table of the chunk, and jump offsets are relative to the next instruction.
+#[derive(Clone, Copy, Debug)]
+pub enum OpCode {
+    Constant(u16),
+    Nil,
+    True,
+    False,
+    Pop,
+    GetLocal(u8),
+    SetLocal(u8),
+    GetGlobal(u16),
+    DefineGlobal(u16),
+    SetGlobal(u16),
+    GetUpvalue(u8),
+    SetUpvalue(u8),
+    GetProperty(u16),
+    SetProperty(u16),
+    GetSuper(u16),
+    GetIndex,
+    SetIndex,
+    /// Replaces the given number of values on top of the stack with a list of them
+    List(u16),
+    Equal,
+    Greater,
+    Less,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Not,
+    Negate,
+    Print,
+    Jump(u16),
+    JumpIfFalse(u16),
+    Loop(u16),
+    Call(u8),
+    Invoke(u16, u8),
+    SuperInvoke(u16, u8),
+    Closure(u16),
+    CloseUpvalue,
+    Return,
+    Class(u16),
+    Inherit,
+    Method(u16),
+}
+
+#[derive(Default)]
+pub struct Chunk {
+    pub code: Vec<OpCode>,
+    pub lines: Vec<usize>,
+    /// The part of the source code that each instruction came from, for runtime errors
+    pub spans: Vec<Span>,
+    pub constants: Vec<Value>,
+}
+
+impl Chunk {
+    /// Appends an instruction and returns its offset.
+    pub fn write(&mut self, op: OpCode, line: usize, span: Span) -> usize {
+        self.code.push(op);
+        self.lines.push(line);
+        self.spans.push(span);
+        self.code.len() - 1
+    }
+
+    pub fn add_constant(&mut self, value: Value) -> usize {
+        self.constants.push(value);
+        self.constants.len() - 1
+    }
+}
@@ -0,0 +1,665 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::bytecode::chunk::OpCode;
+use crate::bytecode::value::{Function, UpvalueDescriptor, Value};
+use crate::diagnostic::{Diagnostic, Phase};
+use crate::error_reporter::ErrorReporter;
+use crate::expr::Expr;
+use crate::literal_value::LiteralValue;
+use crate::span::Span;
+use crate::statement::Statement;
+use crate::token::Token;
+use crate::token_type::TokenType;
+
+/// Compiles a program into a function that runs it when called with no arguments.
+/// The statements must have been resolved without errors. The compiler reports its own
+/// errors (such as exceeding the limits of the instruction set) to the error reporter.
+pub fn compile(
+    statements: &[Statement],
+    error_reporter: &Rc<RefCell<ErrorReporter>>,
+) -> Rc<Function> {
+    let mut compiler = Compiler {
+        states: vec![FunctionState::new(FunctionKind::Script, None, 0)],
+        classes: Vec::new(),
+        error_reporter: error_reporter.clone(),
+        line: 1,
+        span: Span::default(),
+    };
+    for statement in statements {
+        compiler.statement(statement);
+    }
+    compiler.emit_return();
+    Rc::new(compiler.states.pop().unwrap().function)
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum FunctionKind {
+    Script,
+    Function,
+    Method,
+    Initializer,
+}
+
+struct Local {
+    name: String,
+    depth: usize,
+    is_captured: bool,
+}
+
+struct FunctionState {
+    function: Function,
+    kind: FunctionKind,
+    locals: Vec<Local>,
+    scope_depth: usize,
+}
+
+impl FunctionState {
+    fn new(kind: FunctionKind, name: Option<&str>, arity: usize) -> Self {
+        // Slot 0 holds the function being called, or the receiver of a method
+        let slot_zero = Local {
+            name: if matches!(kind, FunctionKind::Method | FunctionKind::Initializer) {
+                "this".to_string()
+            } else {
+                String::new()
+            },
+            depth: 0,
+            is_captured: false,
+        };
+        Self {
+            function: Function {
+                name: name.map(Rc::from),
+                arity,
+                ..Function::default()
+            },
+            kind,
+            locals: vec![slot_zero],
+            scope_depth: 0,
+        }
+    }
+}
+
+struct ClassState {
+    has_superclass: bool,
+}
+
+struct Compiler {
+    states: Vec<FunctionState>,
+    classes: Vec<ClassState>,
+    error_reporter: Rc<RefCell<ErrorReporter>>,
+    line: usize,
+    /// The span that runtime errors in the emitted instructions refer to
+    span: Span,
+}
+
+impl Compiler {
+    fn statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::Class {
+                name,
+                superclass,
+                methods,
+            } => self.class_declaration(name, superclass.as_ref(), methods),
+            Statement::Expression { expr } => {
+                self.expression(expr);
+                self.emit(OpCode::Pop);
+            }
+            Statement::Function { name, params, body } => {
+                self.at(name);
+                let global = self.declare_variable(name);
+                self.function(FunctionKind::Function, name, params, body);
+                self.define_variable(global);
+            }
+            Statement::Return { keyword, expr } => {
+                self.at(keyword);
+                if self.state().kind == FunctionKind::Initializer {
+                    // The resolver does not allow returning a value from an initializer
+                    self.emit_return();
+                } else {
+                    if let Some(expr) = expr {
+                        self.expression(expr);
+                    } else {
+                        self.emit(OpCode::Nil);
+                    }
+                    self.emit(OpCode::Return);
+                }
+            }
+            Statement::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.expression(condition);
+                let then_jump = self.emit(OpCode::JumpIfFalse(0));
+                self.emit(OpCode::Pop);
+                self.statement(then_branch);
+                let else_jump = self.emit(OpCode::Jump(0));
+                self.patch_jump(then_jump);
+                self.emit(OpCode::Pop);
+                if let Some(else_branch) = else_branch {
+                    self.statement(else_branch);
+                }
+                self.patch_jump(else_jump);
+            }
+            Statement::While { condition, block } => {
+                let loop_start = self.chunk_len();
+                self.expression(condition);
+                let exit_jump = self.emit(OpCode::JumpIfFalse(0));
+                self.emit(OpCode::Pop);
+                self.statement(block);
+                self.emit_loop(loop_start);
+                self.patch_jump(exit_jump);
+                self.emit(OpCode::Pop);
+            }
+            Statement::Print { expr } => {
+                self.expression(expr);
+                self.emit(OpCode::Print);
+            }
+            Statement::Block { statements } => {
+                self.begin_scope();
+                for statement in statements {
+                    self.statement(statement);
+                }
+                self.end_scope();
+            }
+            Statement::Var { name, initializer } => {
+                self.at(name);
+                if let Some(initializer) = initializer {
+                    self.expression(initializer);
+                } else {
+                    self.emit(OpCode::Nil);
+                }
+                let global = self.declare_variable(name);
+                self.define_variable(global);
+            }
+        }
+    }
+
+    fn class_declaration(
+        &mut self,
+        name: &Token,
+        superclass: Option<&Expr>,
+        methods: &[Statement],
+    ) {
+        self.at(name);
+        let name_constant = self.identifier_constant(&name.lexeme);
+        let global = self.declare_variable(name);
+        self.emit(OpCode::Class(name_constant));
+        self.define_variable(global);
+        self.classes.push(ClassState {
+            has_superclass: false,
+        });
+
+        if let Some(superclass) = superclass {
+            self.expression(superclass);
+            self.begin_scope();
+            self.add_local("super", name);
+            self.at(name);
+            self.named_variable(name, false);
+            self.emit(OpCode::Inherit);
+            self.classes.last_mut().unwrap().has_superclass = true;
+        }
+
+        self.named_variable(name, false);
+        for method in methods {
+            if let Statement::Function { name, params, body } = method {
+                self.at(name);
+                let method_constant = self.identifier_constant(&name.lexeme);
+                let kind = if name.lexeme == "init" {
+                    FunctionKind::Initializer
+                } else {
+                    FunctionKind::Method
+                };
+                self.function(kind, name, params, body);
+                self.emit(OpCode::Method(method_constant));
+            }
+        }
+        self.emit(OpCode::Pop);
+
+        if self.classes.pop().unwrap().has_superclass {
+            self.end_scope();
+        }
+    }
+
+    fn function(&mut self, kind: FunctionKind, name: &Token, params: &[Token], body: &[Statement]) {
+        self.states
+            .push(FunctionState::new(kind, Some(&name.lexeme), params.len()));
+        self.begin_scope();
+        for param in params {
+            self.add_local(&param.lexeme, param);
+        }
+        for statement in body {
+            self.statement(statement);
+        }
+        self.emit_return();
+        let function = self.states.pop().unwrap().function;
+        let constant = self.make_constant(Value::Function(Rc::new(function)));
+        self.emit(OpCode::Closure(constant));
+    }
+
+    #[allow(clippy::too_many_lines)]
+    fn expression(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Assign {
+                name, expression, ..
+            } => {
+                self.expression(expression);
+                self.at(name);
+                self.named_variable(name, true);
+            }
+            Expr::Binary {
+                left,
+                operator,
+                right,
+            } => {
+                self.expression(left);
+                self.expression(right);
+                self.at(operator);
+                if operator.token_type == TokenType::Plus {
+                    // Operands of the wrong types are reported for the whole expression
+                    self.span = expr.span();
+                }
+                self.binary_operator(operator);
+            }
+            Expr::Call {
+                callee,
+                closing_paren,
+                arguments,
+            } => match callee.as_ref() {
+                Expr::Get { object, name } => {
+                    self.expression(object);
+                    let argc = self.arguments(arguments);
+                    self.at(closing_paren);
+                    // The VM reports a missing method at its name, and other errors at
+                    // the closing parenthesis
+                    self.span = name.span.to(closing_paren.span);
+                    let name = self.identifier_constant(&name.lexeme);
+                    self.emit(OpCode::Invoke(name, argc));
+                }
+                Expr::Super {
+                    keyword, method, ..
+                } => {
+                    self.at(keyword);
+                    self.named_variable(&Self::synthetic_token(keyword, "this"), false);
+                    let argc = self.arguments(arguments);
+                    self.named_variable(&Self::synthetic_token(keyword, "super"), false);
+                    self.at(closing_paren);
+                    self.span = method.span.to(closing_paren.span);
+                    let name = self.identifier_constant(&method.lexeme);
+                    self.emit(OpCode::SuperInvoke(name, argc));
+                }
+                _ => {
+                    self.expression(callee);
+                    let argc = self.arguments(arguments);
+                    self.at(closing_paren);
+                    self.emit(OpCode::Call(argc));
+                }
+            },
+            Expr::Logical {
+                left,
+                operator,
+                right,
+            } => {
+                self.expression(left);
+                self.at(operator);
+                if operator.token_type == TokenType::And {
+                    let end_jump = self.emit(OpCode::JumpIfFalse(0));
+                    self.emit(OpCode::Pop);
+                    self.expression(right);
+                    self.patch_jump(end_jump);
+                } else {
+                    let else_jump = self.emit(OpCode::JumpIfFalse(0));
+                    let end_jump = self.emit(OpCode::Jump(0));
+                    self.patch_jump(else_jump);
+                    self.emit(OpCode::Pop);
+                    self.expression(right);
+                    self.patch_jump(end_jump);
+                }
+            }
+            Expr::Get { object, name } => {
+                self.expression(object);
+                self.at(name);
+                let name = self.identifier_constant(&name.lexeme);
+                self.emit(OpCode::GetProperty(name));
+            }
+            Expr::Grouping { expression, .. } => self.expression(expression),
+            Expr::Index {
+                object,
+                index,
+                closing_bracket,
+            } => {
+                self.expression(object);
+                self.expression(index);
+                self.at(closing_bracket);
+                self.emit(OpCode::GetIndex);
+            }
+            Expr::List { elements, .. } => {
+                for element in elements {
+                    self.expression(element);
+                }
+                let count = u16::try_from(elements.len()).unwrap_or_else(|_| {
+                    self.error_at_line("Too many elements in list");
+                    0
+                });
+                self.emit(OpCode::List(count));
+            }
+            Expr::Literal { value, .. } => match value {
+                LiteralValue::Nil => {
+                    self.emit(OpCode::Nil);
+                }
+                LiteralValue::Bool(true) => {
+                    self.emit(OpCode::True);
+                }
+                LiteralValue::Bool(false) => {
+                    self.emit(OpCode::False);
+                }
+                value => {
+                    let value = Value::try_from(value.clone()).expect("Literals are plain values");
+                    let constant = self.make_constant(value);
+                    self.emit(OpCode::Constant(constant));
+                }
+            },
+            Expr::Set {
+                object,
+                name,
+                value,
+            } => {
+                self.expression(object);
+                self.expression(value);
+                self.at(name);
+                let name = self.identifier_constant(&name.lexeme);
+                self.emit(OpCode::SetProperty(name));
+            }
+            Expr::SetIndex {
+                object,
+                index,
+                closing_bracket,
+                value,
+            } => {
+                self.expression(object);
+                self.expression(index);
+                self.expression(value);
+                self.at(closing_bracket);
+                self.emit(OpCode::SetIndex);
+            }
+            Expr::Super {
+                keyword, method, ..
+            } => {
+                self.at(keyword);
+                self.named_variable(&Self::synthetic_token(keyword, "this"), false);
+                self.named_variable(&Self::synthetic_token(keyword, "super"), false);
+                self.at(method);
+                let name = self.identifier_constant(&method.lexeme);
+                self.emit(OpCode::GetSuper(name));
+            }
+            Expr::This { keyword, .. } => {
+                self.at(keyword);
+                self.named_variable(keyword, false);
+            }
+            Expr::Variable { name, .. } => {
+                self.at(name);
+                self.named_variable(name, false);
+            }
+            Expr::Unary { operator, right } => {
+                self.expression(right);
+                self.at(operator);
+                if operator.token_type == TokenType::Bang {
+                    self.emit(OpCode::Not);
+                } else {
+                    self.emit(OpCode::Negate);
+                }
+            }
+        }
+    }
+
+    fn binary_operator(&mut self, operator: &Token) {
+        match operator.token_type {
+            TokenType::Plus => {
+                self.emit(OpCode::Add);
+            }
+            TokenType::Minus => {
+                self.emit(OpCode::Subtract);
+            }
+            TokenType::Star => {
+                self.emit(OpCode::Multiply);
+            }
+            TokenType::Slash => {
+                self.emit(OpCode::Divide);
+            }
+            TokenType::Greater => {
+                self.emit(OpCode::Greater);
+            }
+            TokenType::GreaterEqual => {
+                self.emit(OpCode::Less);
+                self.emit(OpCode::Not);
+            }
+            TokenType::Less => {
+                self.emit(OpCode::Less);
+            }
+            TokenType::LessEqual => {
+                self.emit(OpCode::Greater);
+                self.emit(OpCode::Not);
+            }
+            TokenType::EqualEqual => {
+                self.emit(OpCode::Equal);
+            }
+            TokenType::BangEqual => {
+                self.emit(OpCode::Equal);
+                self.emit(OpCode::Not);
+            }
+            _ => panic!(
+                "Missing implementation for operator {}",
+                operator.token_type
+            ),
+        }
+    }
+
+    fn arguments(&mut self, arguments: &[Expr]) -> u8 {
+        for argument in arguments {
+            self.expression(argument);
+        }
+        // The parser does not allow more than 255 arguments
+        u8::try_from(arguments.len()).unwrap_or(u8::MAX)
+    }
+
+    fn synthetic_token(token: &Token, lexeme: &str) -> Token {
+        Token::new(
+            token.token_type,
+            lexeme.to_string(),
+            token.line,
+            token.count,
+        )
+    }
+
+    fn named_variable(&mut self, name: &Token, assign: bool) {
+        let current = self.states.len() - 1;
+        let op = if let Some(slot) = self.resolve_local(current, name) {
+            if assign {
+                OpCode::SetLocal(slot)
+            } else {
+                OpCode::GetLocal(slot)
+            }
+        } else if let Some(index) = self.resolve_upvalue(current, name) {
+            if assign {
+                OpCode::SetUpvalue(index)
+            } else {
+                OpCode::GetUpvalue(index)
+            }
+        } else {
+            let constant = self.identifier_constant(&name.lexeme);
+            if assign {
+                OpCode::SetGlobal(constant)
+            } else {
+                OpCode::GetGlobal(constant)
+            }
+        };
+        self.emit(op);
+    }
+
+    fn resolve_local(&mut self, state: usize, name: &Token) -> Option<u8> {
+        self.states[state]
+            .locals
+            .iter()
+            .rposition(|local| local.name == name.lexeme)
+            .map(|slot| u8::try_from(slot).expect("Number of locals is limited"))
+    }
+
+    fn resolve_upvalue(&mut self, state: usize, name: &Token) -> Option<u8> {
+        if state == 0 {
+            return None;
+        }
+        if let Some(slot) = self.resolve_local(state - 1, name) {
+            self.states[state - 1].locals[slot as usize].is_captured = true;
+            return Some(self.add_upvalue(state, slot, true, name));
+        }
+        let index = self.resolve_upvalue(state - 1, name)?;
+        Some(self.add_upvalue(state, index, false, name))
+    }
+
+    fn add_upvalue(&mut self, state: usize, index: u8, is_local: bool, name: &Token) -> u8 {
+        let upvalues = &mut self.states[state].function.upvalues;
+        if let Some(existing) = upvalues
+            .iter()
+            .position(|upvalue| upvalue.is_local == is_local && upvalue.index == index)
+        {
+            return u8::try_from(existing).expect("Number of upvalues is limited");
+        }
+        if upvalues.len() > u8::MAX as usize {
+            self.error(name, "Too many closure variables in function");
+            return 0;
+        }
+        upvalues.push(UpvalueDescriptor { is_local, index });
+        u8::try_from(upvalues.len() - 1).expect("Number of upvalues is limited")
+    }
+
+    /// Declares a local variable, or returns the constant with the name of a global variable.
+    /// Redeclaring a local variable in the same scope has been reported by the resolver.
+    fn declare_variable(&mut self, name: &Token) -> Option<u16> {
+        if self.state().scope_depth == 0 {
+            return Some(self.identifier_constant(&name.lexeme));
+        }
+        self.add_local(&name.lexeme, name);
+        None
+    }
+
+    fn define_variable(&mut self, global: Option<u16>) {
+        if let Some(global) = global {
+            self.emit(OpCode::DefineGlobal(global));
+        }
+    }
+
+    fn add_local(&mut self, name: &str, token: &Token) {
+        if self.state().locals.len() > u8::MAX as usize {
+            self.error(token, "Too many local variables in function");
+            return;
+        }
+        let depth = self.state().scope_depth;
+        self.state_mut().locals.push(Local {
+            name: name.to_string(),
+            depth,
+            is_captured: false,
+        });
+    }
+
+    fn begin_scope(&mut self) {
+        self.state_mut().scope_depth += 1;
+    }
+
+    fn end_scope(&mut self) {
+        self.state_mut().scope_depth -= 1;
+        let scope_depth = self.state().scope_depth;
+        while let Some(local) = self.state().locals.last() {
+            if local.depth <= scope_depth {
+                break;
+            }
+            if local.is_captured {
+                self.emit(OpCode::CloseUpvalue);
+            } else {
+                self.emit(OpCode::Pop);
+            }
+            self.state_mut().locals.pop();
+        }
+    }
+
+    fn identifier_constant(&mut self, name: &str) -> u16 {
+        self.make_constant(Value::String(name.into()))
+    }
+
+    fn make_constant(&mut self, value: Value) -> u16 {
+        let index = self.state_mut().function.chunk.add_constant(value);
+        u16::try_from(index).unwrap_or_else(|_| {
+            self.error_at_line("Too many constants in one chunk");
+            0
+        })
+    }
+
+    /// Makes the following instructions refer to the given token
+    fn at(&mut self, token: &Token) {
+        self.line = token.line;
+        self.span = token.span;
+    }
+
+    fn emit(&mut self, op: OpCode) -> usize {
+        let (line, span) = (self.line, self.span);
+        self.state_mut().function.chunk.write(op, line, span)
+    }
+
+    fn emit_return(&mut self) {
+        if self.state().kind == FunctionKind::Initializer {
+            self.emit(OpCode::GetLocal(0));
+        } else {
+            self.emit(OpCode::Nil);
+        }
+        self.emit(OpCode::Return);
+    }
+
+    fn emit_loop(&mut self, loop_start: usize) {
+        let offset = self.chunk_len() + 1 - loop_start;
+        let offset = u16::try_from(offset).unwrap_or_else(|_| {
+            self.error_at_line("Loop body too large");
+            0
+        });
+        self.emit(OpCode::Loop(offset));
+    }
+
+    /// Sets the target of a jump instruction to the next instruction to be emitted.
+    fn patch_jump(&mut self, jump: usize) {
+        let offset = self.chunk_len() - jump - 1;
+        let offset = u16::try_from(offset).unwrap_or_else(|_| {
+            self.error_at_line("Too much code to jump over");
+            0
+        });
+        let code = &mut self.state_mut().function.chunk.code;
+        code[jump] = match code[jump] {
+            OpCode::Jump(_) => OpCode::Jump(offset),
+            OpCode::JumpIfFalse(_) => OpCode::JumpIfFalse(offset),
+            op => panic!("Can't patch {op:?}"),
+        };
+    }
+
+    fn chunk_len(&self) -> usize {
+        self.state().function.chunk.code.len()
+    }
+
+    fn state(&self) -> &FunctionState {
+        self.states.last().unwrap()
+    }
+
+    fn state_mut(&mut self) -> &mut FunctionState {
+        self.states.last_mut().unwrap()
+    }
+
+    fn error(&mut self, token: &Token, message: &str) {
+        self.error_reporter.borrow_mut().error_with_token(
+            Phase::Compile,
+            Some(token.clone()),
+            message,
+        );
+    }
+
+    fn error_at_line(&mut self, message: &str) {
+        self.error_reporter
+            .borrow_mut()
+            .report(Diagnostic::error(Phase::Compile, message).at_line(self.line));
+    }
+}
@@ -0,0 +1,11 @@
+//! An alternative backend that compiles the syntax tree to bytecode, which is run by a stack
+//! based virtual machine (as in the second part of "Crafting Interpreters").
+
+mod chunk;
+mod compiler;
+mod value;
+mod vm;
+
+pub use compiler::compile;
+pub use value::Value;
+pub use vm::Vm;
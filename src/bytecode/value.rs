@@ -0,0 +1,196 @@
+use std::cell::RefCell;
+use std::fmt::{Display, Formatter};
+use std::rc::Rc;
+
+use rustc_hash::FxHashMap;
+
+use crate::bytecode::chunk::Chunk;
+use crate::error_reporter::{Error, Result};
+use crate::list::{Element, List, ListFunction};
+use crate::literal_value::LiteralValue;
+use crate::lox_callable::LoxCallable;
+
+/// A value on the stack of the virtual machine.
+#[derive(Clone)]
+pub enum Value {
+    Nil,
+    Bool(bool),
+    Number(f64),
+    String(Rc<str>),
+    Function(Rc<Function>),
+    Closure(Rc<Closure>),
+    Native(LoxCallable),
+    ListFunction(ListFunction),
+    Class(Rc<Class>),
+    Instance(Rc<Instance>),
+    BoundMethod(Rc<BoundMethod>),
+    List(List<Value>),
+}
+
+/// A compiled function. The top level script is compiled into a function with no parameters.
+#[derive(Default)]
+pub struct Function {
+    /// None for the top level of the script
+    pub name: Option<Rc<str>>,
+    pub arity: usize,
+    pub chunk: Chunk,
+    pub upvalues: Vec<UpvalueDescriptor>,
+}
+
+/// Tells a closure where to find a captured variable when it is created: either in a local
+/// slot of the enclosing function, or in one of the upvalues of the enclosing function.
+#[derive(Clone, Copy)]
+pub struct UpvalueDescriptor {
+    pub is_local: bool,
+    pub index: u8,
+}
+
+pub struct Closure {
+    pub function: Rc<Function>,
+    pub upvalues: Vec<Rc<RefCell<Upvalue>>>,
+}
+
+/// A captured variable. It refers to a stack slot for as long as the variable is in scope,
+/// after which the value is moved into the upvalue itself.
+pub enum Upvalue {
+    Open(usize),
+    Closed(Value),
+}
+
+pub struct Class {
+    pub name: String,
+    pub methods: RefCell<FxHashMap<Rc<str>, Rc<Closure>>>,
+}
+
+pub struct Instance {
+    pub class: Rc<Class>,
+    pub fields: RefCell<FxHashMap<Rc<str>, Value>>,
+}
+
+pub struct BoundMethod {
+    pub receiver: Value,
+    pub method: Rc<Closure>,
+}
+
+impl Value {
+    pub fn is_truthy(&self) -> bool {
+        match self {
+            Value::Nil => false,
+            Value::Bool(value) => *value,
+            _ => true,
+        }
+    }
+
+    /// Converts the value for use outside the virtual machine. Functions, classes and
+    /// instances declared in Lox code only exist inside the virtual machine. Lists are
+    /// copied, so changes to the copy are not seen by the virtual machine.
+    pub fn to_literal(&self) -> Option<LiteralValue> {
+        match self {
+            Value::Nil => Some(LiteralValue::Nil),
+            Value::Bool(value) => Some(LiteralValue::Bool(*value)),
+            Value::Number(value) => Some(LiteralValue::Number(*value)),
+            Value::String(value) => Some(LiteralValue::String(value.clone())),
+            Value::Native(func) => Some(LiteralValue::Function(func.clone())),
+            Value::List(list) => {
+                let elements: Option<Vec<_>> =
+                    list.borrow().iter().map(Value::to_literal).collect();
+                Some(LiteralValue::List(List::new(elements?)))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Classes and instances of the tree-walker can't be converted, as the VM has its own.
+/// Lists are copied.
+impl TryFrom<LiteralValue> for Value {
+    type Error = Error;
+
+    fn try_from(value: LiteralValue) -> Result<Self> {
+        Ok(match value {
+            LiteralValue::Nil => Value::Nil,
+            LiteralValue::Bool(value) => Value::Bool(value),
+            LiteralValue::Number(value) => Value::Number(value),
+            LiteralValue::String(value) => Value::String(value),
+            LiteralValue::Function(func) => Value::Native(func),
+            LiteralValue::List(list) => {
+                let elements: Result<Vec<_>> =
+                    list.borrow().iter().cloned().map(Value::try_from).collect();
+                Value::List(List::new(elements?))
+            }
+            LiteralValue::Class(_) | LiteralValue::Instance(_) => {
+                return Err(Error {
+                    token: None,
+                    span: None,
+                    message: format!("{value} can't be used by the bytecode VM"),
+                    trace: Vec::new(),
+                })
+            }
+        })
+    }
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Value::Nil, Value::Nil) => true,
+            (Value::Bool(a), Value::Bool(b)) => a == b,
+            (Value::Number(a), Value::Number(b)) => a == b,
+            (Value::String(a), Value::String(b)) => a == b,
+            (Value::Function(a), Value::Function(b)) => Rc::ptr_eq(a, b),
+            (Value::Native(a), Value::Native(b)) => a == b,
+            (Value::Closure(a), Value::Closure(b)) => Rc::ptr_eq(a, b),
+            (Value::Class(a), Value::Class(b)) => Rc::ptr_eq(a, b),
+            (Value::Instance(a), Value::Instance(b)) => Rc::ptr_eq(a, b),
+            (Value::List(a), Value::List(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Nil => write!(f, "nil"),
+            Value::Bool(value) => write!(f, "{value}"),
+            Value::Number(value) => write!(f, "{value}"),
+            Value::String(value) => write!(f, "{value}"),
+            Value::Function(_)
+            | Value::Closure(_)
+            | Value::BoundMethod(_)
+            | Value::ListFunction(_) => write!(f, "<some function>"),
+            Value::Native(func) => write!(f, "{func}"),
+            Value::Class(class) => write!(f, "{}", class.name),
+            Value::Instance(instance) => write!(f, "{} instance", instance.class.name),
+            Value::List(list) => write!(f, "{list}"),
+        }
+    }
+}
+
+impl Element for Value {
+    fn nil() -> Self {
+        Value::Nil
+    }
+
+    fn number(value: f64) -> Self {
+        Value::Number(value)
+    }
+
+    fn list(list: List<Self>) -> Self {
+        Value::List(list)
+    }
+
+    fn as_number(&self) -> Option<f64> {
+        match self {
+            Value::Number(number) => Some(*number),
+            _ => None,
+        }
+    }
+
+    fn as_list(&self) -> Option<&List<Self>> {
+        match self {
+            Value::List(list) => Some(list),
+            _ => None,
+        }
+    }
+}
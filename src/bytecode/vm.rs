@@ -0,0 +1,590 @@
+use std::cell::RefCell;
+use std::io::Write;
+use std::rc::Rc;
+
+use rustc_hash::FxHashMap;
+
+use crate::bytecode::chunk::OpCode;
+use crate::bytecode::value::{BoundMethod, Class, Closure, Function, Instance, Upvalue, Value};
+use crate::environment::Environment;
+use crate::error_reporter::{Error, Result, StackFrame};
+use crate::interpreter::Interpreter;
+use crate::list;
+use crate::list::List;
+use crate::span::Span;
+
+/// The maximum number of active calls, counting the top level of the script. The frames
+/// are on the heap, but a limit still stops runaway recursion. It is the same as the limit
+/// of the tree-walker, so that programs behave the same on both backends.
+const FRAMES_MAX: usize = 1024;
+
+struct CallFrame {
+    closure: Rc<Closure>,
+    ip: usize,
+    /// The stack index of slot 0 of the function
+    slots: usize,
+}
+
+/// A stack based virtual machine that runs compiled Lox code.
+/// Global variables are kept between calls to [`Vm::interpret`].
+pub struct Vm {
+    stack: Vec<Value>,
+    frames: Vec<CallFrame>,
+    globals: FxHashMap<Rc<str>, Value>,
+    open_upvalues: Vec<Rc<RefCell<Upvalue>>>,
+    init_string: Rc<str>,
+    // Native functions are shared with the tree-walker and expect an environment and an
+    // interpreter, even if they don't use them
+    native_environment: Rc<RefCell<Environment>>,
+}
+
+impl Default for Vm {
+    fn default() -> Self {
+        Self {
+            stack: Vec::new(),
+            frames: Vec::with_capacity(FRAMES_MAX),
+            globals: FxHashMap::default(),
+            open_upvalues: Vec::new(),
+            init_string: "init".into(),
+            native_environment: Rc::new(RefCell::new(Environment::default())),
+        }
+    }
+}
+
+impl Vm {
+    pub fn global(&self, name: &str) -> Option<&Value> {
+        self.globals.get(name)
+    }
+
+    pub fn globals(&self) -> impl Iterator<Item = (&Rc<str>, &Value)> {
+        self.globals.iter()
+    }
+
+    pub fn set_global(&mut self, name: &str, value: Value) {
+        self.globals.insert(name.into(), value);
+    }
+
+    pub fn interpret(&mut self, function: Rc<Function>, output: &mut dyn Write) -> Result<()> {
+        let closure = Rc::new(Closure {
+            function,
+            upvalues: Vec::new(),
+        });
+        self.stack.push(Value::Closure(closure.clone()));
+        let result = self
+            .call(closure, 0)
+            .and_then(|()| self.run(output))
+            .map_err(|error| {
+                let mut error = error.or_span(self.span());
+                error.trace = self.stack_trace();
+                error
+            });
+        if result.is_err() {
+            // Closures that escaped into globals may still refer to the stack
+            self.close_upvalues(0);
+            self.stack.clear();
+            self.frames.clear();
+            self.open_upvalues.clear();
+        }
+        result
+    }
+
+    #[allow(clippy::too_many_lines)]
+    fn run(&mut self, output: &mut dyn Write) -> Result<()> {
+        loop {
+            let frame = self.frames.last_mut().expect("No active call frame");
+            let op = frame.closure.function.chunk.code[frame.ip];
+            frame.ip += 1;
+            match op {
+                OpCode::Constant(index) => {
+                    let constant = self.read_constant(index);
+                    self.stack.push(constant);
+                }
+                OpCode::Nil => self.stack.push(Value::Nil),
+                OpCode::True => self.stack.push(Value::Bool(true)),
+                OpCode::False => self.stack.push(Value::Bool(false)),
+                OpCode::Pop => {
+                    self.pop();
+                }
+                OpCode::GetLocal(slot) => {
+                    let value = self.stack[self.frame().slots + slot as usize].clone();
+                    self.stack.push(value);
+                }
+                OpCode::SetLocal(slot) => {
+                    let slot = self.frame().slots + slot as usize;
+                    self.stack[slot] = self.peek(0).clone();
+                }
+                OpCode::GetGlobal(index) => {
+                    let name = self.read_string(index);
+                    let Some(value) = self.globals.get(&name) else {
+                        return Err(runtime_error(format!("Undefined variable {name}")));
+                    };
+                    self.stack.push(value.clone());
+                }
+                OpCode::DefineGlobal(index) => {
+                    let name = self.read_string(index);
+                    let value = self.pop();
+                    self.globals.insert(name, value);
+                }
+                OpCode::SetGlobal(index) => {
+                    let name = self.read_string(index);
+                    let value = self.peek(0).clone();
+                    let Some(global) = self.globals.get_mut(&name) else {
+                        return Err(runtime_error(format!("Variable {name} not defined")));
+                    };
+                    *global = value;
+                }
+                OpCode::GetUpvalue(index) => {
+                    let upvalue = self.frame().closure.upvalues[index as usize].clone();
+                    let value = match &*upvalue.borrow() {
+                        Upvalue::Open(slot) => self.stack[*slot].clone(),
+                        Upvalue::Closed(value) => value.clone(),
+                    };
+                    self.stack.push(value);
+                }
+                OpCode::SetUpvalue(index) => {
+                    let upvalue = self.frame().closure.upvalues[index as usize].clone();
+                    let value = self.peek(0).clone();
+                    match &mut *upvalue.borrow_mut() {
+                        Upvalue::Open(slot) => self.stack[*slot] = value,
+                        Upvalue::Closed(closed) => *closed = value,
+                    };
+                }
+                OpCode::GetProperty(index) => {
+                    let name = self.read_string(index);
+                    let Value::Instance(instance) = self.peek(0).clone() else {
+                        return Err(runtime_error("Only instances have properties".into()));
+                    };
+                    let field = instance.fields.borrow().get(&name).cloned();
+                    if let Some(value) = field {
+                        self.pop();
+                        self.stack.push(value);
+                    } else {
+                        self.bind_method(&instance.class, &name)?;
+                    }
+                }
+                OpCode::SetProperty(index) => {
+                    let name = self.read_string(index);
+                    let Value::Instance(instance) = self.peek(1).clone() else {
+                        return Err(runtime_error("Only instances have fields".into()));
+                    };
+                    let value = self.pop();
+                    instance.fields.borrow_mut().insert(name, value.clone());
+                    self.pop();
+                    self.stack.push(value);
+                }
+                OpCode::GetSuper(index) => {
+                    let name = self.read_string(index);
+                    let Value::Class(superclass) = self.pop() else {
+                        panic!("'super' is not a class");
+                    };
+                    self.bind_method(&superclass, &name)?;
+                }
+                OpCode::GetIndex => {
+                    let index = self.pop();
+                    let object = self.pop();
+                    self.stack.push(list::get(&object, &index)?);
+                }
+                OpCode::SetIndex => {
+                    let value = self.pop();
+                    let index = self.pop();
+                    let object = self.pop();
+                    list::set(&object, &index, value.clone())?;
+                    self.stack.push(value);
+                }
+                OpCode::List(count) => {
+                    let elements = self.stack.split_off(self.stack.len() - count as usize);
+                    self.stack.push(Value::List(List::new(elements)));
+                }
+                OpCode::Equal => {
+                    let b = self.pop();
+                    let a = self.pop();
+                    self.stack.push(Value::Bool(a == b));
+                }
+                OpCode::Greater => {
+                    let (a, b) = self.pop_numbers()?;
+                    self.stack.push(Value::Bool(a > b));
+                }
+                OpCode::Less => {
+                    let (a, b) = self.pop_numbers()?;
+                    self.stack.push(Value::Bool(a < b));
+                }
+                OpCode::Add => {
+                    let b = self.pop();
+                    let a = self.pop();
+                    let result = match (a, b) {
+                        (Value::Number(a), Value::Number(b)) => Value::Number(a + b),
+                        (Value::String(a), Value::String(b)) => {
+                            let mut result = String::with_capacity(a.len() + b.len());
+                            result.push_str(&a);
+                            result.push_str(&b);
+                            Value::String(result.into())
+                        }
+                        (Value::Number(_), b) => {
+                            return Err(runtime_error(format!("{b} is not a number")))
+                        }
+                        (Value::String(_), b) => {
+                            return Err(runtime_error(format!("{b} is not a string")))
+                        }
+                        _ => {
+                            return Err(runtime_error(
+                                "Operands must be two numbers or two strings".into(),
+                            ))
+                        }
+                    };
+                    self.stack.push(result);
+                }
+                OpCode::Subtract => {
+                    let (a, b) = self.pop_numbers()?;
+                    self.stack.push(Value::Number(a - b));
+                }
+                OpCode::Multiply => {
+                    let (a, b) = self.pop_numbers()?;
+                    self.stack.push(Value::Number(a * b));
+                }
+                OpCode::Divide => {
+                    let b = number(&self.pop())?;
+                    if b == 0f64 {
+                        return Err(runtime_error("Division by 0".into()));
+                    }
+                    let a = number(&self.pop())?;
+                    self.stack.push(Value::Number(a / b));
+                }
+                OpCode::Not => {
+                    let value = self.pop();
+                    self.stack.push(Value::Bool(!value.is_truthy()));
+                }
+                OpCode::Negate => {
+                    let value = number(&self.pop())?;
+                    self.stack.push(Value::Number(-value));
+                }
+                OpCode::Print => {
+                    let value = self.pop();
+                    writeln!(output, "{value}").expect("Write to output failed");
+                    output.flush().unwrap();
+                }
+                OpCode::Jump(offset) => self.frame_mut().ip += offset as usize,
+                OpCode::JumpIfFalse(offset) => {
+                    if !self.peek(0).is_truthy() {
+                        self.frame_mut().ip += offset as usize;
+                    }
+                }
+                OpCode::Loop(offset) => self.frame_mut().ip -= offset as usize,
+                OpCode::Call(argc) => {
+                    let callee = self.peek(argc as usize).clone();
+                    self.call_value(callee, argc as usize, output)?;
+                }
+                OpCode::Invoke(index, argc) => {
+                    let name = self.read_string(index);
+                    let span = self.span();
+                    self.invoke(&name, argc as usize, output)
+                        .map_err(|error| error.or_span(closing_paren(span)))?;
+                }
+                OpCode::SuperInvoke(index, argc) => {
+                    let name = self.read_string(index);
+                    let span = self.span();
+                    let Value::Class(superclass) = self.pop() else {
+                        panic!("'super' is not a class");
+                    };
+                    self.invoke_from_class(&superclass, &name, argc as usize)
+                        .map_err(|error| error.or_span(closing_paren(span)))?;
+                }
+                OpCode::Closure(index) => {
+                    let Value::Function(function) = self.read_constant(index) else {
+                        panic!("Closure constant is not a function");
+                    };
+                    let slots = self.frame().slots;
+                    let upvalues = function
+                        .upvalues
+                        .iter()
+                        .map(|upvalue| {
+                            if upvalue.is_local {
+                                self.capture_upvalue(slots + upvalue.index as usize)
+                            } else {
+                                self.frame().closure.upvalues[upvalue.index as usize].clone()
+                            }
+                        })
+                        .collect();
+                    self.stack
+                        .push(Value::Closure(Rc::new(Closure { function, upvalues })));
+                }
+                OpCode::CloseUpvalue => {
+                    self.close_upvalues(self.stack.len() - 1);
+                    self.pop();
+                }
+                OpCode::Return => {
+                    let result = self.pop();
+                    let frame = self.frames.pop().expect("No active call frame");
+                    self.close_upvalues(frame.slots);
+                    self.stack.truncate(frame.slots);
+                    if self.frames.is_empty() {
+                        return Ok(());
+                    }
+                    self.stack.push(result);
+                }
+                OpCode::Class(index) => {
+                    let name = self.read_string(index);
+                    self.stack.push(Value::Class(Rc::new(Class {
+                        name: name.to_string(),
+                        methods: RefCell::default(),
+                    })));
+                }
+                OpCode::Inherit => {
+                    let Value::Class(superclass) = self.peek(1) else {
+                        return Err(runtime_error("Superclass must be a class".into()));
+                    };
+                    let Value::Class(subclass) = self.peek(0) else {
+                        panic!("Subclass is not a class");
+                    };
+                    subclass.methods.borrow_mut().extend(
+                        superclass
+                            .methods
+                            .borrow()
+                            .iter()
+                            .map(|(name, method)| (name.clone(), method.clone())),
+                    );
+                    self.pop();
+                }
+                OpCode::Method(index) => {
+                    let name = self.read_string(index);
+                    let (Value::Class(class), Value::Closure(method)) =
+                        (self.peek(1), self.peek(0))
+                    else {
+                        panic!("Method must be a closure defined on a class");
+                    };
+                    class.methods.borrow_mut().insert(name, method.clone());
+                    self.pop();
+                }
+            }
+        }
+    }
+
+    fn call_value(&mut self, callee: Value, argc: usize, output: &mut dyn Write) -> Result<()> {
+        match callee {
+            Value::Closure(closure) => self.call(closure, argc),
+            Value::Native(func) => {
+                check_arity(func.arity(), argc)?;
+                let arguments = self
+                    .stack
+                    .split_off(self.stack.len() - argc)
+                    .iter()
+                    .map(|argument| {
+                        argument.to_literal().ok_or_else(|| {
+                            runtime_error(format!(
+                                "{argument} can't be passed to a native function"
+                            ))
+                        })
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                self.pop();
+                let mut interpreter = Interpreter {
+                    globals: self.native_environment.clone(),
+                    output,
+                    call_depth: 0,
+                };
+                let result = func.call(arguments, &self.native_environment, &mut interpreter)?;
+                self.stack.push(result.try_into()?);
+                Ok(())
+            }
+            Value::ListFunction(function) => {
+                check_arity(function.arity(), argc)?;
+                let arguments = self.stack.split_off(self.stack.len() - argc);
+                self.pop();
+                let result = function.call(&arguments)?;
+                self.stack.push(result);
+                Ok(())
+            }
+            Value::Class(class) => {
+                let callee_slot = self.stack.len() - argc - 1;
+                self.stack[callee_slot] = Value::Instance(Rc::new(Instance {
+                    class: class.clone(),
+                    fields: RefCell::default(),
+                }));
+                let initializer = class.methods.borrow().get(&self.init_string).cloned();
+                if let Some(initializer) = initializer {
+                    self.call(initializer, argc)
+                } else {
+                    check_arity(0, argc)
+                }
+            }
+            Value::BoundMethod(bound) => {
+                let callee_slot = self.stack.len() - argc - 1;
+                self.stack[callee_slot] = bound.receiver.clone();
+                self.call(bound.method.clone(), argc)
+            }
+            _ => Err(runtime_error("Can only call functions and classes".into())),
+        }
+    }
+
+    fn call(&mut self, closure: Rc<Closure>, argc: usize) -> Result<()> {
+        check_arity(closure.function.arity, argc)?;
+        if self.frames.len() == FRAMES_MAX {
+            return Err(runtime_error("Stack overflow".into()));
+        }
+        self.frames.push(CallFrame {
+            closure,
+            ip: 0,
+            slots: self.stack.len() - argc - 1,
+        });
+        Ok(())
+    }
+
+    fn invoke(&mut self, name: &Rc<str>, argc: usize, output: &mut dyn Write) -> Result<()> {
+        let Value::Instance(instance) = self.peek(argc).clone() else {
+            return Err(self.method_error(name, "Only instances have properties".into()));
+        };
+        let field = instance.fields.borrow().get(name).cloned();
+        if let Some(field) = field {
+            let callee_slot = self.stack.len() - argc - 1;
+            self.stack[callee_slot] = field.clone();
+            self.call_value(field, argc, output)
+        } else {
+            self.invoke_from_class(&instance.class, name, argc)
+        }
+    }
+
+    fn invoke_from_class(&mut self, class: &Rc<Class>, name: &Rc<str>, argc: usize) -> Result<()> {
+        let method = class.methods.borrow().get(name).cloned();
+        let Some(method) = method else {
+            return Err(self.method_error(name, format!("Undefined property '{name}'")));
+        };
+        self.call(method, argc)
+    }
+
+    /// Replaces the instance on top of the stack with one of its methods bound to it.
+    fn bind_method(&mut self, class: &Rc<Class>, name: &Rc<str>) -> Result<()> {
+        let method = class.methods.borrow().get(name).cloned();
+        let Some(method) = method else {
+            return Err(runtime_error(format!("Undefined property '{name}'")));
+        };
+        let receiver = self.pop();
+        self.stack.push(Value::BoundMethod(Rc::new(BoundMethod {
+            receiver,
+            method,
+        })));
+        Ok(())
+    }
+
+    fn capture_upvalue(&mut self, slot: usize) -> Rc<RefCell<Upvalue>> {
+        if let Some(upvalue) = self
+            .open_upvalues
+            .iter()
+            .find(|upvalue| matches!(*upvalue.borrow(), Upvalue::Open(open) if open == slot))
+        {
+            return upvalue.clone();
+        }
+        let upvalue = Rc::new(RefCell::new(Upvalue::Open(slot)));
+        self.open_upvalues.push(upvalue.clone());
+        upvalue
+    }
+
+    /// Moves the values of all captured variables at or above the given stack slot into
+    /// their upvalues, as the slots are about to be discarded.
+    fn close_upvalues(&mut self, first_slot: usize) {
+        let stack = &self.stack;
+        self.open_upvalues.retain(|upvalue| {
+            let slot = match *upvalue.borrow() {
+                Upvalue::Open(slot) => slot,
+                Upvalue::Closed(_) => return false,
+            };
+            if slot >= first_slot {
+                *upvalue.borrow_mut() = Upvalue::Closed(stack[slot].clone());
+                false
+            } else {
+                true
+            }
+        });
+    }
+
+    /// The span of the instruction being executed
+    fn span(&self) -> Span {
+        let frame = self.frame();
+        frame.closure.function.chunk.spans[frame.ip.saturating_sub(1)]
+    }
+
+    /// An error about the method named by the `Invoke` or `SuperInvoke` instruction being
+    /// executed. The span of these instructions starts with the name of the method.
+    fn method_error(&self, name: &str, message: String) -> Error {
+        let span = self.span();
+        runtime_error(message).or_span(Span::new(span.start, span.start + name.len()))
+    }
+
+    fn frame(&self) -> &CallFrame {
+        self.frames.last().expect("No active call frame")
+    }
+
+    fn frame_mut(&mut self) -> &mut CallFrame {
+        self.frames.last_mut().expect("No active call frame")
+    }
+
+    fn read_constant(&self, index: u16) -> Value {
+        self.frame().closure.function.chunk.constants[index as usize].clone()
+    }
+
+    fn read_string(&self, index: u16) -> Rc<str> {
+        match self.read_constant(index) {
+            Value::String(string) => string,
+            _ => panic!("Constant is not a string"),
+        }
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack.pop().expect("Stack underflow")
+    }
+
+    fn peek(&self, distance: usize) -> &Value {
+        &self.stack[self.stack.len() - 1 - distance]
+    }
+
+    fn pop_numbers(&mut self) -> Result<(f64, f64)> {
+        let b = self.pop();
+        let a = self.pop();
+        Ok((number(&a)?, number(&b)?))
+    }
+
+    /// The line being executed in each active function, innermost first
+    fn stack_trace(&self) -> Vec<StackFrame> {
+        self.frames
+            .iter()
+            .rev()
+            .map(|frame| {
+                let function = &frame.closure.function;
+                StackFrame {
+                    function: function.name.as_deref().map(str::to_string),
+                    line: function.chunk.lines[frame.ip.saturating_sub(1)],
+                }
+            })
+            .collect()
+    }
+}
+
+fn number(value: &Value) -> Result<f64> {
+    if let Value::Number(number) = value {
+        Ok(*number)
+    } else {
+        Err(runtime_error(format!("{value} is not a number")))
+    }
+}
+
+fn check_arity(arity: usize, argc: usize) -> Result<()> {
+    if arity == argc {
+        Ok(())
+    } else {
+        Err(runtime_error(format!(
+            "Wrong number of arguments to function. Got {argc} but function requires {arity}"
+        )))
+    }
+}
+
+/// The span of the closing parenthesis at the end of the span of a call
+fn closing_paren(span: Span) -> Span {
+    Span::new(span.end.saturating_sub(1), span.end)
+}
+
+fn runtime_error(message: String) -> Error {
+    Error {
+        token: None,
+        span: None,
+        message,
+        trace: Vec::new(),
+    }
+}
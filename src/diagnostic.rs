@@ -0,0 +1,400 @@
+use std::fmt::{self, Display, Formatter, Write};
+
+use crate::span::Span;
+use crate::token::Token;
+
+use crate::error_reporter::{Error, StackFrame};
+
+/// How serious a diagnostic is. Only errors stop the code from being executed.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// The step of evaluating source code that found the problem.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Phase {
+    Scan,
+    Parse,
+    Resolve,
+    /// Compiling the syntax tree to bytecode
+    Compile,
+    Runtime,
+}
+
+impl Display for Phase {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Phase::Scan => write!(f, "scan"),
+            Phase::Parse => write!(f, "parse"),
+            Phase::Resolve => write!(f, "resolve"),
+            Phase::Compile => write!(f, "compile"),
+            Phase::Runtime => write!(f, "runtime"),
+        }
+    }
+}
+
+/// A problem found in the source code, with enough information to show where it is.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub phase: Phase,
+    pub message: String,
+    /// The line of the problem, or 0 if it is not known
+    pub line: usize,
+    /// The column of the problem counting characters from 1, or 0 if it is not known
+    pub column: usize,
+    pub span: Option<Span>,
+    /// The token the problem was found at, if any
+    pub token: Option<Token>,
+    pub notes: Vec<String>,
+    pub help: Option<String>,
+    /// The Lox functions that were executing when a runtime error happened, innermost first
+    pub trace: Vec<StackFrame>,
+}
+
+impl Diagnostic {
+    pub fn error(phase: Phase, message: &str) -> Self {
+        Self {
+            severity: Severity::Error,
+            phase,
+            message: message.to_string(),
+            line: 0,
+            column: 0,
+            span: None,
+            token: None,
+            notes: Vec::new(),
+            help: None,
+            trace: Vec::new(),
+        }
+    }
+
+    pub fn warning(phase: Phase, message: &str) -> Self {
+        Self {
+            severity: Severity::Warning,
+            ..Self::error(phase, message)
+        }
+    }
+
+    /// Converts an error that stopped execution
+    pub fn runtime_error(error: &Error) -> Self {
+        let diagnostic = Self::error(Phase::Runtime, &error.message);
+        let mut diagnostic = match &error.token {
+            Some(token) => diagnostic.at_token(token),
+            None => diagnostic.at_line(error.trace.first().map_or(0, |frame| frame.line)),
+        };
+        diagnostic.span = error.span.or(diagnostic.span);
+        diagnostic.trace.clone_from(&error.trace);
+        diagnostic
+    }
+
+    #[must_use]
+    pub fn at_line(mut self, line: usize) -> Self {
+        self.line = line;
+        self
+    }
+
+    #[must_use]
+    pub fn at(mut self, line: usize, column: usize, span: Span) -> Self {
+        self.line = line;
+        self.column = column;
+        self.span = Some(span);
+        self
+    }
+
+    #[must_use]
+    pub fn at_token(mut self, token: &Token) -> Self {
+        self.token = Some(token.clone());
+        self.at(token.line, token.column, token.span)
+    }
+
+    #[must_use]
+    pub fn with_note(mut self, note: &str) -> Self {
+        self.notes.push(note.to_string());
+        self
+    }
+
+    #[must_use]
+    pub fn with_help(mut self, help: &str) -> Self {
+        self.help = Some(help.to_string());
+        self
+    }
+
+    /// Serializes the diagnostic as a single line JSON object. Unknown lines and columns
+    /// are null, and the stack trace is only included for runtime errors.
+    pub fn to_json(&self, file_name: &str) -> String {
+        let mut json = format!(
+            "{{\"file\":{},\"line\":{},\"column\":{},\"severity\":\"{}\",\"phase\":\"{}\",\"message\":{}",
+            json_string(file_name),
+            json_position(self.line),
+            json_position(self.column),
+            self.severity,
+            self.phase,
+            json_string(&self.message)
+        );
+        if self.phase == Phase::Runtime {
+            let frames: Vec<_> = self
+                .trace
+                .iter()
+                .map(|frame| {
+                    format!(
+                        "{{\"function\":{},\"line\":{}}}",
+                        frame.function.as_deref().map_or("null".into(), json_string),
+                        json_position(frame.line)
+                    )
+                })
+                .collect();
+            write!(json, ",\"trace\":[{}]", frames.join(",")).unwrap();
+        }
+        json.push('}');
+        json
+    }
+}
+
+fn json_position(value: usize) -> String {
+    if value == 0 {
+        "null".to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut json = String::with_capacity(s.len() + 2);
+    json.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if c.is_control() => write!(json, "\\u{:04x}", c as u32).unwrap(),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const RED: &str = "\x1b[1;31m";
+const YELLOW: &str = "\x1b[1;33m";
+const BLUE: &str = "\x1b[1;34m";
+
+/// Renders diagnostics in the style of rustc, with the offending source code underlined:
+///
+/// ```text
+/// error: Expected expression
+///  --> script.lox:1:10
+///   |
+/// 1 | print 1 +;
+///   |          ^
+/// ```
+pub struct Renderer<'a> {
+    pub file_name: &'a str,
+    pub source: &'a str,
+    /// Use ANSI escape codes for colors
+    pub color: bool,
+}
+
+impl Renderer<'_> {
+    pub fn render(&self, diagnostic: &Diagnostic) -> String {
+        let mut output = String::new();
+        let line = self.source_line(diagnostic.line);
+        let gutter = " ".repeat(diagnostic.line.to_string().len());
+        writeln!(
+            output,
+            "{}{}{}: {}{}{}",
+            self.style(severity_color(diagnostic.severity)),
+            diagnostic.severity,
+            self.style(RESET),
+            self.style(BOLD),
+            diagnostic.message,
+            self.style(RESET)
+        )
+        .unwrap();
+        write!(
+            output,
+            "{gutter}{}-->{} {}",
+            self.style(BLUE),
+            self.style(RESET),
+            self.file_name
+        )
+        .unwrap();
+        if diagnostic.line > 0 {
+            write!(output, ":{}", diagnostic.line).unwrap();
+            if diagnostic.column > 0 {
+                write!(output, ":{}", diagnostic.column).unwrap();
+            }
+        }
+        output.push('\n');
+        if let Some(line) = line {
+            let bar = format!("{}|{}", self.style(BLUE), self.style(RESET));
+            writeln!(output, "{gutter} {bar}").unwrap();
+            writeln!(
+                output,
+                "{}{}{} {bar} {line}",
+                self.style(BLUE),
+                diagnostic.line,
+                self.style(RESET)
+            )
+            .unwrap();
+            if diagnostic.column > 0 {
+                writeln!(
+                    output,
+                    "{gutter} {bar} {}{}{}{}",
+                    indentation(line, diagnostic.column),
+                    self.style(severity_color(diagnostic.severity)),
+                    "^".repeat(self.underline_length(diagnostic)),
+                    self.style(RESET)
+                )
+                .unwrap();
+            }
+        }
+        for note in &diagnostic.notes {
+            writeln!(
+                output,
+                "{gutter} {}={} note: {note}",
+                self.style(BLUE),
+                self.style(RESET)
+            )
+            .unwrap();
+        }
+        if let Some(help) = &diagnostic.help {
+            writeln!(
+                output,
+                "{gutter} {}={} help: {help}",
+                self.style(BLUE),
+                self.style(RESET)
+            )
+            .unwrap();
+        }
+        for frame in &diagnostic.trace {
+            writeln!(output, "{frame}").unwrap();
+        }
+        output
+    }
+
+    fn source_line(&self, line: usize) -> Option<&str> {
+        if line == 0 {
+            None
+        } else {
+            self.source.lines().nth(line - 1)
+        }
+    }
+
+    /// The number of characters of the span on its first line, and at least one
+    fn underline_length(&self, diagnostic: &Diagnostic) -> usize {
+        diagnostic
+            .span
+            .and_then(|span| self.source.get(span.start..span.end))
+            .map_or(1, |text| {
+                text.chars().take_while(|c| *c != '\n').count().max(1)
+            })
+    }
+
+    fn style(&self, style: &'static str) -> &'static str {
+        if self.color {
+            style
+        } else {
+            ""
+        }
+    }
+}
+
+fn severity_color(severity: Severity) -> &'static str {
+    match severity {
+        Severity::Error => RED,
+        Severity::Warning => YELLOW,
+    }
+}
+
+/// Whitespace that lines up with the given column of the line, keeping any tabs
+fn indentation(line: &str, column: usize) -> String {
+    line.chars()
+        .chain(std::iter::repeat(' '))
+        .take(column - 1)
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::diagnostic::{Diagnostic, Phase};
+    use crate::error_reporter::ErrorReporter;
+    use crate::span::Span;
+    use crate::Lox;
+
+    #[test]
+    fn render_diagnostic() {
+        let source = "var a = 1;\n{ var b = b + a; }\n";
+        let reporter = ErrorReporter::new("test.lox", source);
+        let diagnostic = Diagnostic::error(
+            Phase::Resolve,
+            "Can't read local variable in its own initializer",
+        )
+        .at(2, 11, Span::new(19, 20))
+        .with_note("a note")
+        .with_help("some help");
+        assert_eq!(
+            reporter.render(&diagnostic),
+            "\
+error: Can't read local variable in its own initializer
+ --> test.lox:2:11
+  |
+2 | { var b = b + a; }
+  |           ^
+  = note: a note
+  = help: some help
+"
+        );
+    }
+
+    #[test]
+    fn render_diagnostic_underlines_span() {
+        let reporter = ErrorReporter::new("test.lox", "print \"abc\" + \n");
+        let diagnostic =
+            Diagnostic::error(Phase::Scan, "Unterminated string").at(1, 7, Span::new(6, 11));
+        assert_eq!(
+            reporter.render(&diagnostic),
+            "\
+error: Unterminated string
+ --> test.lox:1:7
+  |
+1 | print \"abc\" + 
+  |       ^^^^^
+"
+        );
+        assert_eq!(
+            reporter.render(&Diagnostic::error(Phase::Compile, "Something").at_line(0)),
+            "error: Something\n --> test.lox\n"
+        );
+    }
+
+    #[test]
+    fn diagnostic_to_json() {
+        let mut lox = Lox::with_output(Vec::new());
+        assert!(lox.eval("\n  var x = 1 +;").is_err());
+        assert_eq!(
+            lox.diagnostics()[0].to_json("dir\\\"quoted\".lox"),
+            r#"{"file":"dir\\\"quoted\".lox","line":2,"column":14,"severity":"error","phase":"parse","message":"Expected expression"}"#
+        );
+        assert!(lox.eval("print -nil;").is_err());
+        assert_eq!(
+            lox.diagnostics()[0].to_json("x.lox"),
+            r#"{"file":"x.lox","line":1,"column":7,"severity":"error","phase":"runtime","message":"nil is not a number","trace":[{"function":null,"line":1}]}"#
+        );
+    }
+}
@@ -3,70 +3,99 @@ use std::rc::Rc;
 
 use crate::error_reporter;
 use crate::error_reporter::Error;
+use crate::expr::Slot;
 use crate::literal_value::LiteralValue;
 use crate::token::Token;
 use rustc_hash::FxHashMap;
 
+/// The variables of a scope. The outermost environment holds the global variables by name,
+/// and the others hold local variables in the slots given to them by the resolver.
 #[derive(Default)]
 pub struct Environment {
-    values: FxHashMap<String, LiteralValue>,
+    globals: FxHashMap<String, LiteralValue>,
+    /// Local variables, in the order they were declared
+    locals: Vec<LiteralValue>,
     enclosing: Option<Rc<RefCell<Environment>>>,
 }
 
 impl Environment {
     pub fn from_parent(enclosing: &Rc<RefCell<Environment>>) -> Self {
         Self {
-            values: FxHashMap::default(),
+            globals: FxHashMap::default(),
+            locals: Vec::new(),
             enclosing: Some(enclosing.clone()),
         }
     }
 
-    pub fn define(&mut self, name: &Token, value: LiteralValue) -> error_reporter::Result<()> {
-        match self.values.insert(name.lexeme.clone(), value) {
-            None => Ok(()),
-            Some(_) => Err(Error {
-                token: Some(name.clone()),
-                message: format!("Variable {name} already defined"),
-            }),
+    /// The global variables, if this is the outermost environment
+    pub fn values(&self) -> impl Iterator<Item = (&String, &LiteralValue)> {
+        self.globals.iter()
+    }
+
+    /// Defines a variable. In the global environment, this replaces any variable with the
+    /// same name. Otherwise the variable gets the next slot, as redeclaring a local variable
+    /// is reported by the resolver.
+    pub fn define(&mut self, name: &str, value: LiteralValue) {
+        if self.enclosing.is_some() {
+            self.locals.push(value);
+        } else {
+            self.globals.insert(name.to_string(), value);
         }
     }
 
+    /// Assigns to a global variable
     pub fn assign(&mut self, name: &Token, new_value: LiteralValue) -> error_reporter::Result<()> {
-        if let Some(current_value) = self.values.get_mut(&name.lexeme) {
+        if let Some(current_value) = self.globals.get_mut(&name.lexeme) {
             *current_value = new_value;
             return Ok(());
         };
 
-        if let Some(ref mut enclosing) = self.enclosing {
-            return (**enclosing).borrow_mut().assign(name, new_value);
-        }
-
         Err(Error {
-            token: None,
-            message: format!("Variable {name} not defined"),
+            token: Some(Box::new(name.clone())),
+            span: Some(name.span),
+            message: format!("Variable {} not defined", name.lexeme),
+            trace: Vec::new(),
         })
     }
 
+    /// Gets a global variable
     pub fn get(&self, name: &Token) -> error_reporter::Result<LiteralValue> {
-        if let Some(value) = self.values.get(&name.lexeme) {
+        if let Some(value) = self.globals.get(&name.lexeme) {
             return Ok(value.clone());
         }
 
-        if let Some(ref enclosing) = self.enclosing {
-            return enclosing.borrow().get(name);
-        }
-
         Err(Error {
-            token: None,
+            token: Some(Box::new(name.clone())),
+            span: Some(name.span),
             message: format!("Undefined variable {}", name.lexeme),
+            trace: Vec::new(),
         })
     }
 
-    pub fn get_at(&self, distance: usize, name: &Token) -> error_reporter::Result<LiteralValue> {
-        if distance == 0 {
-            Ok(self.get(name)?)
+    pub fn get_at(&self, slot: Slot) -> LiteralValue {
+        if slot.depth == 0 {
+            self.locals[slot.index].clone()
+        } else if let Some(enclosing) = &self.enclosing {
+            (*enclosing).borrow().get_at(Slot {
+                depth: slot.depth - 1,
+                ..slot
+            })
+        } else {
+            panic!("Incorrect distance!")
+        }
+    }
+
+    pub fn assign_at(&mut self, slot: Slot, new_value: LiteralValue) {
+        if slot.depth == 0 {
+            self.locals[slot.index] = new_value;
         } else if let Some(enclosing) = &self.enclosing {
-            (*enclosing).borrow().get_at(distance - 1, name)
+            (**enclosing).borrow_mut().assign_at(
+                Slot {
+                    depth: slot.depth - 1,
+                    ..slot
+                },
+                new_value,
+            );
         } else {
             panic!("Incorrect distance!")
         }
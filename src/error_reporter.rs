@@ -1,39 +1,219 @@
+use std::fmt::{self, Display, Formatter};
+use std::io::{self, IsTerminal};
 use std::result;
 
+use crate::diagnostic::{Diagnostic, Phase, Renderer, Severity};
+use crate::span::Span;
 use crate::token::Token;
 
+/// Collects the problems found in the source code. Diagnostics can be rendered showing
+/// the offending code, as the source is known.
 #[derive(Clone, Default)]
 pub struct ErrorReporter {
-    has_error: bool,
+    diagnostics: Vec<Diagnostic>,
+    file_name: String,
+    source: String,
+    color: bool,
+    /// The comments found by the scanner and their lines, which can allow warnings
+    comments: Vec<(usize, String)>,
 }
 
 impl ErrorReporter {
-    pub fn error(&mut self, line: usize, message: &str) {
-        self.report(line, "", message);
+    /// Creates a reporter for errors in the given source code, which is shown in reports
+    /// as coming from the file with the given name. Reports are colored if stderr is a
+    /// terminal.
+    pub fn new(file_name: &str, source: &str) -> Self {
+        Self {
+            diagnostics: Vec::new(),
+            file_name: file_name.to_string(),
+            source: source.to_string(),
+            color: io::stderr().is_terminal(),
+            comments: Vec::new(),
+        }
     }
 
-    pub fn error_with_token(&mut self, token: Option<Token>, message: &str) {
+    /// Reports an error at the token, or at the end of the source code if there is no token.
+    pub fn error_with_token(&mut self, phase: Phase, token: Option<Token>, message: &str) {
+        let diagnostic = Diagnostic::error(phase, message);
         if let Some(token) = token {
-            self.report(token.line, format!("at '{token}' ").as_ref(), message);
+            self.report(diagnostic.at_token(&token));
         } else {
-            println!("Error: {message}");
+            self.report(self.at_end(diagnostic));
+        }
+    }
+
+    pub fn report(&mut self, diagnostic: Diagnostic) {
+        self.diagnostics.push(diagnostic);
+    }
+
+    /// Records a comment that starts on the given line, including its `//`.
+    pub fn comment(&mut self, line: usize, text: String) {
+        self.comments.push((line, text));
+    }
+
+    /// Reports a warning from the given lint, unless it is suppressed by a
+    /// `// lox-allow(<lint>)` comment on the line of the warning or the line before it.
+    pub fn warn(&mut self, lint: &str, diagnostic: Diagnostic) {
+        let allow = format!("// lox-allow({lint})");
+        let allowed = self.comments.iter().any(|(line, text)| {
+            (diagnostic.line.saturating_sub(1)..=diagnostic.line).contains(line)
+                && text.contains(&allow)
+        });
+        if !allowed {
+            self.report(diagnostic);
+        }
+    }
+
+    /// Turns all warnings reported so far into errors.
+    pub fn deny_warnings(&mut self) {
+        for diagnostic in &mut self.diagnostics {
+            diagnostic.severity = Severity::Error;
         }
     }
 
-    pub fn report(&mut self, line: usize, loc: &str, message: &str) {
-        println!("[line {line}] Error {loc}: {message}");
-        self.has_error = true;
+    pub fn render(&self, diagnostic: &Diagnostic) -> String {
+        Renderer {
+            file_name: &self.file_name,
+            source: &self.source,
+            color: self.color,
+        }
+        .render(diagnostic)
+    }
+
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
     }
 
     pub fn has_error(&self) -> bool {
-        self.has_error
+        self.diagnostics
+            .iter()
+            .any(|diagnostic| diagnostic.severity == Severity::Error)
+    }
+
+    /// Places the diagnostic at a span of the source code.
+    pub fn at_span(&self, diagnostic: Diagnostic, span: Span) -> Diagnostic {
+        let (line, column) = self.line_and_column(span.start);
+        diagnostic.at(line, column, span)
+    }
+
+    /// The line and column of a byte offset in the source code, both counting from 1.
+    pub fn line_and_column(&self, offset: usize) -> (usize, usize) {
+        let before = &self.source[..offset.min(self.source.len())];
+        let line_start = before.rfind('\n').map_or(0, |index| index + 1);
+        (
+            before.matches('\n').count() + 1,
+            before[line_start..].chars().count() + 1,
+        )
+    }
+
+    fn at_end(&self, diagnostic: Diagnostic) -> Diagnostic {
+        let end = self.source.trim_end();
+        let line = end.lines().count().max(1);
+        let column = end.lines().last().unwrap_or_default().chars().count() + 1;
+        diagnostic.at(line, column, Span::new(end.len(), end.len()))
     }
 }
 
 #[derive(Debug)]
 pub struct Error {
-    pub token: Option<Token>,
+    /// Boxed to keep results small
+    pub token: Option<Box<Token>>,
+    /// The part of the source code that the error refers to. This is usually the span of the
+    /// token, but may cover a whole expression.
+    pub span: Option<Span>,
     pub message: String,
+    /// The Lox functions that were executing when a runtime error happened, innermost first.
+    /// Empty until the error has been returned from the interpreter.
+    pub trace: Vec<StackFrame>,
+}
+
+impl Error {
+    /// Gives the error a location if it does not have one already
+    pub fn or_at(mut self, token: &Token) -> Self {
+        if self.token.is_none() {
+            self.span = self.span.or(Some(token.span));
+            self.token = Some(Box::new(token.clone()));
+        }
+        self
+    }
+
+    /// Gives the error a span if it does not have one already
+    pub fn or_span(mut self, span: Span) -> Self {
+        self.span = self.span.or(Some(span));
+        self
+    }
+
+    /// Records that the error is passing out of a call to the named function, made on the
+    /// given line. The last frame of the trace is the one being unwound, and is named when
+    /// the error passes out of it.
+    pub fn unwind(&mut self, function: &str, call_line: usize) {
+        self.end_trace();
+        self.trace.last_mut().unwrap().function = Some(function.to_string());
+        self.trace.push(StackFrame {
+            function: None,
+            line: call_line,
+        });
+    }
+
+    /// Makes sure the trace has at least the frame where the error happened
+    pub fn end_trace(&mut self) {
+        if self.trace.is_empty() {
+            self.trace.push(StackFrame {
+                function: None,
+                line: self.token.as_ref().map_or(0, |token| token.line),
+            });
+        }
+    }
+}
+
+/// A function that was executing when a runtime error happened
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct StackFrame {
+    /// The name of the function, or None for the top level of the script
+    pub function: Option<String>,
+    /// The line that was being executed in the function
+    pub line: usize,
+}
+
+impl Display for StackFrame {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match &self.function {
+            Some(function) => write!(f, "[line {}] in {function}()", self.line),
+            None => write!(f, "[line {}] in script", self.line),
+        }
+    }
 }
 
 pub type Result<T> = result::Result<T, Error>;
+
+#[cfg(test)]
+mod test {
+    use crate::span::Span;
+    use crate::{EvalError, Lox};
+
+    #[test]
+    fn runtime_error_span() {
+        let mut lox = Lox::with_output(Vec::new());
+        let Err(EvalError::Runtime(error)) = lox.eval("print (nil) + 1;") else {
+            panic!("Expected runtime error");
+        };
+        assert_eq!(error.span, Some(Span::new(6, 15)));
+        assert_eq!(
+            error.token.map(|token| (token.line, token.column)),
+            Some((1, 13))
+        );
+    }
+
+    #[test]
+    fn only_comments_allow_warnings() {
+        let mut lox = Lox::with_output(Vec::new());
+        lox.eval(
+            "fun f(a) { print \"// lox-allow(unused)\"; }
+            fun g(b) { // lox-allow(unused)
+            }",
+        )
+        .unwrap();
+        let messages: Vec<_> = lox.diagnostics().iter().map(|d| &d.message).collect();
+        assert_eq!(messages, ["Unused parameter 'a'"]);
+    }
+}
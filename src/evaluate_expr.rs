@@ -1,11 +1,16 @@
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::rc::Rc;
 
 use crate::environment::Environment;
 use crate::error_reporter::{Error, Result};
-use crate::expr::Expr;
-use crate::interpreter::Interpreter;
+use crate::expr::{Expr, Slot};
+use crate::interpreter::{Interpreter, MAX_CALL_DEPTH};
+use crate::list;
+use crate::list::List;
 use crate::literal_value::LiteralValue;
+use crate::lox_class::LoxClass;
+use crate::lox_instance::LoxInstance;
+use crate::span::Span;
 use crate::token::Token;
 use crate::token_type::TokenType;
 
@@ -25,9 +30,19 @@ impl EvaluateExpr for Expr {
         interpreter: &mut Interpreter,
     ) -> Result<LiteralValue> {
         match self {
-            Expr::Assign { name, expression } => {
+            Expr::Assign {
+                name,
+                expression,
+                slot,
+            } => {
                 let value = expression.evaluate(environment, interpreter)?;
-                environment.borrow_mut().assign(name, value.clone())?;
+                if let Some(slot) = slot.get() {
+                    (**environment).borrow_mut().assign_at(slot, value.clone());
+                } else {
+                    (*interpreter.globals)
+                        .borrow_mut()
+                        .assign(name, value.clone())?;
+                }
                 Ok(value)
             }
             Expr::Binary {
@@ -37,56 +52,7 @@ impl EvaluateExpr for Expr {
             } => {
                 let left = left.evaluate(environment, interpreter)?;
                 let right = right.evaluate(environment, interpreter)?;
-                match operator.token_type {
-                    TokenType::Minus => Ok(LiteralValue::Number(
-                        f64::try_from(left)? - f64::try_from(right)?,
-                    )),
-                    TokenType::Plus => match left {
-                        LiteralValue::Number(left) => {
-                            Ok(LiteralValue::Number(left + f64::try_from(right)?))
-                        }
-                        LiteralValue::String(mut left) => {
-                            left.push_str(try_into_str(&right)?);
-                            Ok(LiteralValue::String(left))
-                        }
-                        _ => Err(Error {
-                            token: None,
-                            message: "Operands must be two numbers or two strings".into(),
-                        }),
-                    },
-                    TokenType::Slash => {
-                        let right = f64::try_from(right)?;
-                        if right == 0f64 {
-                            Err(Error {
-                                token: Some(operator.clone()),
-                                message: "Division by 0".into(),
-                            })
-                        } else {
-                            Ok(LiteralValue::Number(f64::try_from(left)? / right))
-                        }
-                    }
-                    TokenType::Star => Ok(LiteralValue::Number(
-                        f64::try_from(left)? * f64::try_from(right)?,
-                    )),
-                    TokenType::Greater => Ok(LiteralValue::Bool(
-                        f64::try_from(left)? > f64::try_from(right)?,
-                    )),
-                    TokenType::GreaterEqual => Ok(LiteralValue::Bool(
-                        f64::try_from(left)? >= f64::try_from(right)?,
-                    )),
-                    TokenType::Less => Ok(LiteralValue::Bool(
-                        f64::try_from(left)? < f64::try_from(right)?,
-                    )),
-                    TokenType::LessEqual => Ok(LiteralValue::Bool(
-                        f64::try_from(left)? <= f64::try_from(right)?,
-                    )),
-                    TokenType::EqualEqual => Ok(LiteralValue::Bool(is_equal(&left, &right))),
-                    TokenType::BangEqual => Ok(LiteralValue::Bool(!is_equal(&left, &right))),
-                    _ => panic!(
-                        "Missing implementation for operator {}",
-                        operator.token_type
-                    ),
-                }
+                binary(operator, left, right, self.span()).map_err(|error| error.or_at(operator))
             }
             Expr::Call {
                 callee,
@@ -98,24 +64,39 @@ impl EvaluateExpr for Expr {
                     .iter()
                     .map(|arg| arg.evaluate(environment, interpreter))
                     .collect::<Result<Vec<_>>>()?;
-                if let LiteralValue::Function(func) = callee_value {
-                    if func.arity() == argument_values.len() {
-                        Ok(func.call(argument_values, environment, interpreter)?)
-                    } else {
-                        Err(Error {
-                            token: Some(closing_paren.clone()),
-                            message: format!(
-                                "Wrong number of arguments to function. Got {} but function requires {}",
-                                argument_values.len(),
-                                func.arity()
-                            ),
-                        })
+                match callee_value {
+                    LiteralValue::Function(func) => {
+                        check_arity(func.arity(), argument_values.len(), closing_paren)?;
+                        if func.is_native() {
+                            func.call(argument_values, environment, interpreter)
+                                .map_err(|error| error.or_at(closing_paren))
+                        } else {
+                            call_frame(interpreter, func.name(), closing_paren, |interpreter| {
+                                func.call(argument_values, environment, interpreter)
+                            })
+                        }
                     }
-                } else {
-                    Err(Error {
-                        token: Some(closing_paren.clone()),
-                        message: "Can only call functions".into(),
-                    })
+                    LiteralValue::Class(class) => {
+                        check_arity(class.arity(), argument_values.len(), closing_paren)?;
+                        if class.find_method("init").is_some() {
+                            call_frame(interpreter, "init", closing_paren, |interpreter| {
+                                LoxClass::instantiate(
+                                    &class,
+                                    argument_values,
+                                    environment,
+                                    interpreter,
+                                )
+                            })
+                        } else {
+                            LoxClass::instantiate(&class, argument_values, environment, interpreter)
+                        }
+                    }
+                    _ => Err(Error {
+                        token: Some(Box::new(closing_paren.clone())),
+                        span: Some(closing_paren.span),
+                        message: "Can only call functions and classes".into(),
+                        trace: Vec::new(),
+                    }),
                 }
             }
             Expr::Logical {
@@ -123,40 +104,115 @@ impl EvaluateExpr for Expr {
                 operator,
                 right,
             } => {
-                let left: bool = left.evaluate(environment, interpreter)?.try_into()?;
+                let left = left.evaluate(environment, interpreter)?;
                 match operator.token_type {
                     TokenType::Or => {
-                        if left {
-                            Ok(LiteralValue::Bool(true))
+                        if left.is_truthy() {
+                            Ok(left)
                         } else {
-                            Ok(LiteralValue::Bool(
-                                right.evaluate(environment, interpreter)?.try_into()?,
-                            ))
+                            right.evaluate(environment, interpreter)
                         }
                     }
                     TokenType::And => {
-                        if left {
-                            Ok(LiteralValue::Bool(
-                                right.evaluate(environment, interpreter)?.try_into()?,
-                            ))
+                        if left.is_truthy() {
+                            right.evaluate(environment, interpreter)
                         } else {
-                            Ok(LiteralValue::Bool(false))
+                            Ok(left)
                         }
                     }
                     _ => panic!("Unsupported binary operator: {}", operator.token_type),
                 }
             }
-            Expr::Grouping { expression } => expression.evaluate(environment, interpreter),
-            Expr::Literal { value } => Ok(value.clone()),
-            Expr::Variable { name } => lookup_variable(environment, interpreter, name),
-            Expr::Unary { operator, right } => match operator.token_type {
-                TokenType::Bang => {
-                    let boolean_value: bool =
-                        right.evaluate(environment, interpreter)?.try_into()?;
-                    Ok(LiteralValue::Bool(!boolean_value))
+            Expr::Get { object, name } => match object.evaluate(environment, interpreter)? {
+                LiteralValue::Instance(instance) => LoxInstance::get(&instance, name),
+                _ => Err(Error {
+                    token: Some(Box::new(name.clone())),
+                    span: Some(name.span),
+                    message: "Only instances have properties".into(),
+                    trace: Vec::new(),
+                }),
+            },
+            Expr::Grouping { expression, .. } => expression.evaluate(environment, interpreter),
+            Expr::Index {
+                object,
+                index,
+                closing_bracket,
+            } => {
+                let object = object.evaluate(environment, interpreter)?;
+                let index = index.evaluate(environment, interpreter)?;
+                list::get(&object, &index).map_err(|error| error.or_at(closing_bracket))
+            }
+            Expr::Literal { value, .. } => Ok(value.clone()),
+            Expr::List { elements, .. } => {
+                let elements = elements
+                    .iter()
+                    .map(|element| element.evaluate(environment, interpreter))
+                    .collect::<Result<Vec<_>>>()?;
+                Ok(LiteralValue::List(List::new(elements)))
+            }
+            Expr::Set {
+                object,
+                name,
+                value,
+            } => {
+                let LiteralValue::Instance(instance) = object.evaluate(environment, interpreter)?
+                else {
+                    return Err(Error {
+                        token: Some(Box::new(name.clone())),
+                        span: Some(name.span),
+                        message: "Only instances have fields".into(),
+                        trace: Vec::new(),
+                    });
+                };
+                let value = value.evaluate(environment, interpreter)?;
+                instance.borrow_mut().set(name, value.clone());
+                Ok(value)
+            }
+            Expr::SetIndex {
+                object,
+                index,
+                closing_bracket,
+                value,
+            } => {
+                let object = object.evaluate(environment, interpreter)?;
+                let index = index.evaluate(environment, interpreter)?;
+                let value = value.evaluate(environment, interpreter)?;
+                list::set(&object, &index, value.clone())
+                    .map_err(|error| error.or_at(closing_bracket))?;
+                Ok(value)
+            }
+            Expr::Super { method, slot, .. } => {
+                let slot = slot.get().expect("'super' was not resolved");
+                let superclass = (**environment).borrow().get_at(slot);
+                // 'this' is the only variable in the scope inside the one with 'super'
+                let this = (**environment).borrow().get_at(Slot {
+                    depth: slot.depth - 1,
+                    index: 0,
+                });
+                match superclass {
+                    LiteralValue::Class(superclass) => superclass
+                        .bind_method(&method.lexeme, this)
+                        .map(LiteralValue::Function)
+                        .ok_or_else(|| Error {
+                            token: Some(Box::new(method.clone())),
+                            span: Some(method.span),
+                            message: format!("Undefined property '{}'", method.lexeme),
+                            trace: Vec::new(),
+                        }),
+                    _ => panic!("'super' is not a class"),
                 }
+            }
+            Expr::This { keyword, slot } => {
+                lookup_variable(environment, interpreter, keyword, slot)
+            }
+            Expr::Variable { name, slot } => lookup_variable(environment, interpreter, name, slot),
+            Expr::Unary { operator, right } => match operator.token_type {
+                TokenType::Bang => Ok(LiteralValue::Bool(
+                    !right.evaluate(environment, interpreter)?.is_truthy(),
+                )),
                 TokenType::Minus => {
-                    let number: f64 = right.evaluate(environment, interpreter)?.try_into()?;
+                    let number = f64::try_from(right.evaluate(environment, interpreter)?)
+                        .map_err(|error| error.or_at(operator))?;
                     Ok(LiteralValue::Number(-number))
                 }
                 _ => {
@@ -170,18 +226,134 @@ impl EvaluateExpr for Expr {
     }
 }
 
+/// Applies a binary operator to two values, without the location of any error
+pub fn binary(
+    operator: &Token,
+    left: LiteralValue,
+    right: LiteralValue,
+    span: Span,
+) -> Result<LiteralValue> {
+    match operator.token_type {
+        TokenType::Minus => Ok(LiteralValue::Number(
+            f64::try_from(left)? - f64::try_from(right)?,
+        )),
+        TokenType::Plus => match left {
+            // Operands of the wrong types are reported for the whole expression
+            LiteralValue::Number(left) => Ok(LiteralValue::Number(
+                left + f64::try_from(right).map_err(|error| error.or_span(span))?,
+            )),
+            LiteralValue::String(left) => {
+                let right = try_into_str(&right).map_err(|error| error.or_span(span))?;
+                let mut string = String::with_capacity(left.len() + right.len());
+                string.push_str(&left);
+                string.push_str(right);
+                Ok(LiteralValue::String(string.into()))
+            }
+            _ => Err(Error {
+                token: Some(Box::new(operator.clone())),
+                span: Some(span),
+                message: "Operands must be two numbers or two strings".into(),
+                trace: Vec::new(),
+            }),
+        },
+        TokenType::Slash => {
+            let right = f64::try_from(right)?;
+            if right == 0f64 {
+                Err(Error {
+                    token: Some(Box::new(operator.clone())),
+                    span: Some(operator.span),
+                    message: "Division by 0".into(),
+                    trace: Vec::new(),
+                })
+            } else {
+                Ok(LiteralValue::Number(f64::try_from(left)? / right))
+            }
+        }
+        TokenType::Star => Ok(LiteralValue::Number(
+            f64::try_from(left)? * f64::try_from(right)?,
+        )),
+        TokenType::Greater => Ok(LiteralValue::Bool(
+            f64::try_from(left)? > f64::try_from(right)?,
+        )),
+        TokenType::GreaterEqual => Ok(LiteralValue::Bool(
+            f64::try_from(left)? >= f64::try_from(right)?,
+        )),
+        TokenType::Less => Ok(LiteralValue::Bool(
+            f64::try_from(left)? < f64::try_from(right)?,
+        )),
+        TokenType::LessEqual => Ok(LiteralValue::Bool(
+            f64::try_from(left)? <= f64::try_from(right)?,
+        )),
+        TokenType::EqualEqual => Ok(LiteralValue::Bool(is_equal(&left, &right))),
+        TokenType::BangEqual => Ok(LiteralValue::Bool(!is_equal(&left, &right))),
+        _ => panic!(
+            "Missing implementation for operator {}",
+            operator.token_type
+        ),
+    }
+}
+
 fn lookup_variable(
     environment: &Rc<RefCell<Environment>>,
     interpreter: &Interpreter,
     name: &Token,
+    slot: &Cell<Option<Slot>>,
 ) -> Result<LiteralValue> {
-    if let Some(distance) = interpreter.resolver.get(name) {
-        (**environment).borrow().get_at(*distance, name)
+    if let Some(slot) = slot.get() {
+        Ok((**environment).borrow().get_at(slot))
     } else {
         (*interpreter.globals).borrow().get(name)
     }
 }
 
+/// When less of the Rust stack than this is left at a call, the call runs on a new stack
+const STACK_RED_ZONE: usize = 1024 * 1024;
+/// The size of each stack that is added for deep recursion
+const STACK_SEGMENT: usize = 16 * 1024 * 1024;
+
+/// Runs a call to a Lox function in a new call frame, which is added to the stack trace of
+/// any error
+fn call_frame(
+    interpreter: &mut Interpreter,
+    name: &str,
+    closing_paren: &Token,
+    call: impl FnOnce(&mut Interpreter) -> Result<LiteralValue>,
+) -> Result<LiteralValue> {
+    // The top level of the script is also a frame
+    if interpreter.call_depth + 1 == MAX_CALL_DEPTH {
+        return Err(Error {
+            token: Some(Box::new(closing_paren.clone())),
+            span: Some(closing_paren.span),
+            message: "Stack overflow".into(),
+            trace: Vec::new(),
+        });
+    }
+    interpreter.call_depth += 1;
+    // Each call of the tree-walker takes a lot of the Rust stack, so it is grown as needed
+    // rather than relying on the thread the interpreter happens to run on
+    let result = stacker::maybe_grow(STACK_RED_ZONE, STACK_SEGMENT, || call(interpreter));
+    interpreter.call_depth -= 1;
+    result.map_err(|mut error| {
+        error.unwind(name, closing_paren.line);
+        error
+    })
+}
+
+fn check_arity(arity: usize, num_arguments: usize, closing_paren: &Token) -> Result<()> {
+    if arity == num_arguments {
+        Ok(())
+    } else {
+        Err(Error {
+            token: Some(Box::new(closing_paren.clone())),
+            span: Some(closing_paren.span),
+            message: format!(
+                "Wrong number of arguments to function. Got {num_arguments} but function requires {arity}"
+            ),
+            trace: Vec::new(),
+        })
+    }
+}
+
 fn is_equal(left: &LiteralValue, right: &LiteralValue) -> bool {
     if matches!(left, LiteralValue::Nil) {
         return matches!(right, LiteralValue::Nil);
@@ -195,6 +367,8 @@ fn try_into_str(value: &LiteralValue) -> Result<&str> {
     }
     Err(Error {
         token: None,
+        span: None,
         message: format!("{value} is not a string"),
+        trace: Vec::new(),
     })
 }
@@ -8,7 +8,9 @@ use crate::evaluate_expr::EvaluateExpr;
 use crate::interpreter::Interpreter;
 use crate::literal_value::LiteralValue;
 use crate::lox_callable::LoxCallable;
+use crate::lox_class::LoxClass;
 use crate::statement::Statement;
+use rustc_hash::FxHashMap;
 
 pub enum ErrorOrReturn {
     Error(error_reporter::Error),
@@ -32,16 +34,67 @@ impl ExecuteStatement for Statement {
         interpreter: &mut Interpreter,
     ) -> Result<()> {
         match self {
+            Statement::Class {
+                name,
+                superclass,
+                methods,
+            } => {
+                let superclass = if let Some(superclass) = superclass {
+                    if let LiteralValue::Class(superclass) =
+                        superclass.evaluate(environment, interpreter)?
+                    {
+                        Some(superclass)
+                    } else {
+                        return Err(ErrorOrReturn::Error(error_reporter::Error {
+                            token: Some(Box::new(name.clone())),
+                            span: Some(name.span),
+                            message: "Superclass must be a class".into(),
+                            trace: Vec::new(),
+                        }));
+                    }
+                } else {
+                    None
+                };
+                let closure = if let Some(superclass) = &superclass {
+                    let super_env = Rc::new(RefCell::new(Environment::from_parent(environment)));
+                    (*super_env)
+                        .borrow_mut()
+                        .define("super", LiteralValue::Class(superclass.clone()));
+                    super_env
+                } else {
+                    environment.clone()
+                };
+                let mut class_methods = FxHashMap::default();
+                for method in methods {
+                    if let Statement::Function { name, params, body } = method {
+                        class_methods.insert(
+                            name.lexeme.clone(),
+                            LoxCallable::from_statement(
+                                &name.lexeme,
+                                params.clone(),
+                                body.clone(),
+                                &closure,
+                            ),
+                        );
+                    }
+                }
+                let class = LoxClass::new(name.lexeme.clone(), superclass, class_methods);
+                (*environment)
+                    .borrow_mut()
+                    .define(&name.lexeme, LiteralValue::Class(Rc::new(class)));
+            }
             Statement::Expression { expr } => {
                 expr.evaluate(environment, interpreter)?;
             }
             Statement::Function { name, params, body } => (*environment).borrow_mut().define(
-                name,
+                &name.lexeme,
                 LiteralValue::Function(LoxCallable::from_statement(
+                    &name.lexeme,
                     params.clone(),
-                    (*body).clone(),
+                    body.clone(),
+                    environment,
                 )),
-            )?,
+            ),
             Statement::Return { expr, .. } => {
                 return Err(ErrorOrReturn::Return(if let Some(expr) = expr {
                     expr.evaluate(environment, interpreter)?
@@ -54,14 +107,14 @@ impl ExecuteStatement for Statement {
                 then_branch,
                 else_branch,
             } => {
-                if condition.evaluate(environment, interpreter)? == LiteralValue::Bool(true) {
+                if condition.evaluate(environment, interpreter)?.is_truthy() {
                     then_branch.execute(environment, interpreter)?;
                 } else if let Some(else_branch) = else_branch {
                     else_branch.execute(environment, interpreter)?;
                 }
             }
             Statement::While { condition, block } => {
-                while condition.evaluate(environment, interpreter)? == LiteralValue::Bool(true) {
+                while condition.evaluate(environment, interpreter)?.is_truthy() {
                     block.execute(environment, interpreter)?;
                 }
             }
@@ -82,7 +135,7 @@ impl ExecuteStatement for Statement {
                 } else {
                     LiteralValue::Nil
                 };
-                (**environment).borrow_mut().define(name, value)?;
+                (**environment).borrow_mut().define(&name.lexeme, value);
             }
         }
         Ok(())
@@ -1,13 +1,26 @@
+use std::cell::Cell;
 use std::fmt::{Display, Formatter};
 
 use crate::literal_value::LiteralValue;
+use crate::span::Span;
 use crate::token::Token;
 
+/// Where the resolver found a local variable: the number of environments out from the
+/// current one, and the index of the variable in that environment.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Slot {
+    pub depth: usize,
+    pub index: usize,
+}
+
+/// Variables, `this` and `super` have a slot that is set by the resolver if they are local.
+/// Otherwise they are global.
 #[derive(Clone)]
 pub enum Expr {
     Assign {
         name: Token,
         expression: Box<Expr>,
+        slot: Cell<Option<Slot>>,
     },
     Binary {
         left: Box<Expr>,
@@ -24,14 +37,50 @@ pub enum Expr {
         operator: Token,
         right: Box<Expr>,
     },
+    Get {
+        object: Box<Expr>,
+        name: Token,
+    },
     Grouping {
         expression: Box<Expr>,
+        span: Span,
+    },
+    Index {
+        object: Box<Expr>,
+        index: Box<Expr>,
+        closing_bracket: Token,
     },
     Literal {
         value: LiteralValue,
+        span: Span,
+    },
+    List {
+        elements: Vec<Expr>,
+        span: Span,
+    },
+    Set {
+        object: Box<Expr>,
+        name: Token,
+        value: Box<Expr>,
+    },
+    SetIndex {
+        object: Box<Expr>,
+        index: Box<Expr>,
+        closing_bracket: Token,
+        value: Box<Expr>,
+    },
+    Super {
+        keyword: Token,
+        method: Token,
+        slot: Cell<Option<Slot>>,
+    },
+    This {
+        keyword: Token,
+        slot: Cell<Option<Slot>>,
     },
     Variable {
         name: Token,
+        slot: Cell<Option<Slot>>,
     },
     Unary {
         operator: Token,
@@ -39,10 +88,49 @@ pub enum Expr {
     },
 }
 
+impl Expr {
+    /// The part of the source code that the expression was parsed from.
+    pub fn span(&self) -> Span {
+        match self {
+            Expr::Assign {
+                name, expression, ..
+            } => name.span.to(expression.span()),
+            Expr::Binary { left, right, .. } | Expr::Logical { left, right, .. } => {
+                left.span().to(right.span())
+            }
+            Expr::Call {
+                callee,
+                closing_paren,
+                ..
+            } => callee.span().to(closing_paren.span),
+            Expr::Get { object, name } => object.span().to(name.span),
+            Expr::Grouping { span, .. } | Expr::Literal { span, .. } | Expr::List { span, .. } => {
+                *span
+            }
+            Expr::Index {
+                object,
+                closing_bracket,
+                ..
+            } => object.span().to(closing_bracket.span),
+            Expr::Set { object, value, .. } | Expr::SetIndex { object, value, .. } => {
+                object.span().to(value.span())
+            }
+            Expr::Super {
+                keyword, method, ..
+            } => keyword.span.to(method.span),
+            Expr::This { keyword, .. } => keyword.span,
+            Expr::Variable { name, .. } => name.span,
+            Expr::Unary { operator, right } => operator.span.to(right.span()),
+        }
+    }
+}
+
 impl Display for Expr {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
-            Expr::Assign { name, expression } => write!(f, "({name} = {expression})"),
+            Expr::Assign {
+                name, expression, ..
+            } => write!(f, "({name} = {expression})"),
             Expr::Binary {
                 operator,
                 left,
@@ -54,10 +142,35 @@ impl Display for Expr {
                 right,
             } => write!(f, "({} {} {})", operator.token_type, left, right),
             Expr::Call { .. } => write!(f, "call"),
-            Expr::Grouping { expression } => write!(f, "(group {expression})"),
-            Expr::Literal { value } => write!(f, "{value}"),
+            Expr::Get { object, name } => write!(f, "({object}.{})", name.lexeme),
+            Expr::Grouping { expression, .. } => write!(f, "(group {expression})"),
+            Expr::Index { object, index, .. } => write!(f, "({object}[{index}])"),
+            Expr::Literal { value, .. } => write!(f, "{value}"),
+            Expr::List { elements, .. } => {
+                write!(f, "[")?;
+                for (i, element) in elements.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{element}")?;
+                }
+                write!(f, "]")
+            }
             Expr::Unary { operator, right } => write!(f, "({} {})", operator.lexeme, right),
-            Expr::Variable { name } => write!(f, "{name}"),
+            Expr::Set {
+                object,
+                name,
+                value,
+            } => write!(f, "({object}.{} = {value})", name.lexeme),
+            Expr::SetIndex {
+                object,
+                index,
+                value,
+                ..
+            } => write!(f, "({object}[{index}] = {value})"),
+            Expr::Super { method, .. } => write!(f, "(super.{})", method.lexeme),
+            Expr::This { .. } => write!(f, "this"),
+            Expr::Variable { name, .. } => write!(f, "{name}"),
         }
     }
 }
@@ -1,11 +1,14 @@
+use crate::environment::Environment;
 use std::cell::RefCell;
-use crate::resolver::ResolveLookup;
 use std::io::Write;
 use std::rc::Rc;
-use crate::environment::Environment;
+
+/// The maximum number of active calls, counting the top level of the script
+pub const MAX_CALL_DEPTH: usize = 1024;
 
 pub struct Interpreter<'a> {
     pub globals: Rc<RefCell<Environment>>,
-    pub resolver: ResolveLookup,
     pub output: &'a mut dyn Write,
+    /// The number of Lox functions being executed
+    pub call_depth: usize,
 }
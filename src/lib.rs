@@ -0,0 +1,1486 @@
+//! An interpreter for the Lox language from the book "Crafting Interpreters".
+//!
+//! The [`Lox`] type is a session that keeps its global variables between calls to
+//! [`Lox::eval`], so it can be used both for running whole scripts and for a REPL.
+
+use std::cell::RefCell;
+use std::io::{Stdout, Write};
+use std::rc::Rc;
+use std::{fmt, io};
+
+use crate::builtins::add_builtin_functions;
+use crate::bytecode::{Value, Vm};
+use crate::environment::Environment;
+use crate::error_reporter::ErrorReporter;
+use crate::exec_stmt::{ErrorOrReturn, ExecuteStatement};
+use crate::expr::Expr;
+use crate::interpreter::Interpreter;
+use crate::list::ListFunction;
+use crate::native_function::native_function;
+use crate::parser::Parser;
+use crate::scanner::TokenScanner;
+use crate::statement::Statement;
+use crate::token_type::TokenType;
+
+pub use crate::diagnostic::{Diagnostic, Phase, Severity};
+pub use crate::error_reporter::{Error, StackFrame};
+pub use crate::list::List;
+pub use crate::literal_value::LiteralValue;
+pub use crate::lox_callable::LoxCallable;
+pub use crate::lox_class::LoxClass;
+pub use crate::lox_instance::LoxInstance;
+pub use crate::native_function::{FromLox, IntoLox, NativeFunction};
+pub use crate::span::Span;
+pub use crate::token::Token;
+
+mod builtins;
+mod bytecode;
+mod diagnostic;
+mod environment;
+mod error_reporter;
+mod evaluate_expr;
+mod exec_stmt;
+mod expr;
+mod interpreter;
+mod list;
+mod literal_value;
+mod lox_callable;
+mod lox_class;
+mod lox_instance;
+mod native_function;
+mod optimizer;
+mod parser;
+mod resolver;
+mod scanner;
+mod span;
+mod statement;
+mod token;
+mod token_type;
+
+/// The ways Lox code can be executed.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Backend {
+    /// Executes the syntax tree directly.
+    #[default]
+    TreeWalker,
+    /// Compiles the syntax tree to bytecode, which is run by a virtual machine.
+    Bytecode,
+}
+
+/// The ways evaluating a piece of source code can fail.
+#[derive(Debug)]
+pub enum EvalError {
+    /// Errors were found while scanning, parsing or resolving, so nothing was executed.
+    /// The errors are available from [`Lox::diagnostics`].
+    Static,
+    /// Execution stopped because of an error at runtime. The error has a stack trace.
+    Runtime(Error),
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EvalError::Static => write!(f, "Static error"),
+            EvalError::Runtime(error) => {
+                write!(f, "Runtime error: {}", error.message)?;
+                for frame in &error.trace {
+                    write!(f, "\n{frame}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for EvalError {}
+
+/// An interpreter session. Globals defined by one call to [`Lox::eval`] are visible to the
+/// next, and everything printed by the script is written to the output sink.
+pub struct Lox<W: Write = Stdout> {
+    backend: Backend,
+    globals: Rc<RefCell<Environment>>,
+    vm: Vm,
+    echo_expressions: bool,
+    deny_warnings: bool,
+    optimize: bool,
+    /// Holds the diagnostics from the latest evaluation
+    reporter: ErrorReporter,
+    output: W,
+}
+
+impl Lox<Stdout> {
+    pub fn new() -> Self {
+        Self::with_output(io::stdout())
+    }
+}
+
+impl Default for Lox<Stdout> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<W: Write> Lox<W> {
+    pub fn with_output(output: W) -> Self {
+        Self::with_backend(output, Backend::default())
+    }
+
+    pub fn with_backend(output: W, backend: Backend) -> Self {
+        let mut lox = Self {
+            backend,
+            globals: Rc::new(RefCell::new(Environment::default())),
+            vm: Vm::default(),
+            echo_expressions: false,
+            deny_warnings: false,
+            optimize: false,
+            reporter: ErrorReporter::default(),
+            output,
+        };
+        add_builtin_functions(&mut lox);
+        lox
+    }
+
+    /// Scans, parses, resolves and executes the source code.
+    pub fn eval(&mut self, source: &str) -> Result<(), EvalError> {
+        self.eval_named("<input>", source)
+    }
+
+    /// Like [`Lox::eval`], but errors in the source code are reported as coming from the
+    /// file with the given name.
+    pub fn eval_named(&mut self, file_name: &str, source: &str) -> Result<(), EvalError> {
+        let error = Rc::new(RefCell::new(ErrorReporter::new(file_name, source)));
+        let result = self.run(source, &error);
+        self.reporter = error.take();
+        if let Err(EvalError::Runtime(error)) = &result {
+            // The location is that of the span, which may cover more than the token of
+            // the error, so that both backends report the same location
+            let diagnostic = Diagnostic::runtime_error(error);
+            let diagnostic = match error.span {
+                Some(span) => self.reporter.at_span(diagnostic, span),
+                None => diagnostic,
+            };
+            self.reporter.report(diagnostic);
+        }
+        result
+    }
+
+    fn run(&mut self, source: &str, error: &Rc<RefCell<ErrorReporter>>) -> Result<(), EvalError> {
+        let tokens = source.chars().tokens(error.clone());
+        let mut parser = Parser::new(tokens, error.clone());
+        let mut statements = parser.parse();
+        // The statements around a syntax error are incomplete, so resolving them would only
+        // report more errors
+        if error.borrow().has_error() {
+            return Err(EvalError::Static);
+        }
+        if self.echo_expressions {
+            statements = statements.into_iter().map(echo_expression).collect();
+        }
+        resolver::resolve(&statements, error);
+        if self.deny_warnings {
+            error.borrow_mut().deny_warnings();
+        }
+        if error.borrow().has_error() {
+            return Err(EvalError::Static);
+        }
+        if self.optimize {
+            statements = optimizer::optimize(statements);
+        }
+        match self.backend {
+            Backend::TreeWalker => {
+                let mut interpreter = Interpreter {
+                    globals: self.globals.clone(),
+                    output: &mut self.output,
+                    call_depth: 0,
+                };
+                for statement in statements {
+                    if let Err(ErrorOrReturn::Error(mut error)) =
+                        statement.execute(&self.globals, &mut interpreter)
+                    {
+                        error.end_trace();
+                        return Err(EvalError::Runtime(error));
+                    }
+                }
+                Ok(())
+            }
+            Backend::Bytecode => {
+                let function = bytecode::compile(&statements, error);
+                if error.borrow().has_error() {
+                    return Err(EvalError::Static);
+                }
+                self.vm
+                    .interpret(function, &mut self.output)
+                    .map_err(EvalError::Runtime)
+            }
+        }
+    }
+
+    /// Forgets all global variables, including functions registered with
+    /// [`Lox::register_fn`], and restores the builtin functions.
+    pub fn reset(&mut self) {
+        self.globals = Rc::new(RefCell::new(Environment::default()));
+        self.vm = Vm::default();
+        self.reporter = ErrorReporter::default();
+        add_builtin_functions(self);
+    }
+
+    /// Makes expression statements at the top level print their value, as in a REPL.
+    /// Assignments are not printed.
+    pub fn set_echo_expressions(&mut self, echo: bool) {
+        self.echo_expressions = echo;
+    }
+
+    /// Treats warnings, such as unused variables, as errors that stop the code from
+    /// being executed.
+    pub fn set_deny_warnings(&mut self, deny: bool) {
+        self.deny_warnings = deny;
+    }
+
+    /// Simplifies the code before executing it, by computing constant expressions and
+    /// leaving out branches that can never run. Runtime errors are not affected.
+    pub fn set_optimize(&mut self, optimize: bool) {
+        self.optimize = optimize;
+    }
+
+    /// The names and printed values of the global variables, sorted by name
+    pub fn globals(&self) -> Vec<(String, String)> {
+        let mut globals: Vec<_> = match self.backend {
+            Backend::TreeWalker => (*self.globals)
+                .borrow()
+                .values()
+                .map(|(name, value)| (name.clone(), value.to_string()))
+                .collect(),
+            Backend::Bytecode => self
+                .vm
+                .globals()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+        };
+        globals.sort();
+        globals
+    }
+
+    /// Gets the value of a global variable. With the bytecode backend, functions, classes
+    /// and instances declared in Lox can't be accessed this way, and lists are copied.
+    pub fn global(&self, name: &str) -> Option<LiteralValue> {
+        match self.backend {
+            Backend::TreeWalker => (*self.globals).borrow().get(&global_token(name)).ok(),
+            Backend::Bytecode => self.vm.global(name).and_then(Value::to_literal),
+        }
+    }
+
+    /// Sets a global variable, defining it if it does not exist. With the bytecode backend,
+    /// classes and instances of the tree-walker can't be used, and lists are copied.
+    pub fn set_global(&mut self, name: &str, value: LiteralValue) -> Result<(), Error> {
+        if self.backend == Backend::Bytecode {
+            self.vm.set_global(name, value.try_into()?);
+            return Ok(());
+        }
+        (*self.globals).borrow_mut().define(name, value);
+        Ok(())
+    }
+
+    /// Makes a Rust function or closure callable from Lox as a global function.
+    /// Arguments are converted with [`FromLox`] and the result with [`IntoLox`]:
+    ///
+    /// ```
+    /// let mut lox = rlox::Lox::with_output(Vec::new());
+    /// lox.register_fn("repeat", |s: String, n: f64| s.repeat(n as usize));
+    /// lox.eval("print repeat(\"ab\", 3);").unwrap();
+    /// assert_eq!(lox.output(), b"ababab\n");
+    /// ```
+    ///
+    /// With the bytecode backend, functions, classes and instances declared in Lox can't be
+    /// passed to a native function, and lists are passed as copies. Passing them is a
+    /// runtime error.
+    pub fn register_fn<Args>(&mut self, name: &str, func: impl NativeFunction<Args> + 'static) {
+        self.set_global(name, native_function(name, func))
+            .expect("Native functions can be used by both backends");
+    }
+
+    /// Defines one of the functions on lists, which each backend calls with its own values
+    fn define_list_function(&mut self, function: ListFunction) {
+        match self.backend {
+            Backend::TreeWalker => (*self.globals)
+                .borrow_mut()
+                .define(function.name(), builtins::list_function(function)),
+            Backend::Bytecode => self
+                .vm
+                .set_global(function.name(), Value::ListFunction(function)),
+        }
+    }
+
+    /// The errors and warnings found by the latest call to [`Lox::eval`], including any
+    /// error that stopped execution.
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        self.reporter.diagnostics()
+    }
+
+    /// Renders a diagnostic from the latest evaluation with the offending source code,
+    /// in the style of rustc.
+    pub fn render(&self, diagnostic: &Diagnostic) -> String {
+        self.reporter.render(diagnostic)
+    }
+
+    pub fn output(&self) -> &W {
+        &self.output
+    }
+
+    pub fn output_mut(&mut self) -> &mut W {
+        &mut self.output
+    }
+
+    pub fn into_output(self) -> W {
+        self.output
+    }
+}
+
+/// Tells if the source code ends inside a string or with unclosed parentheses, braces or
+/// brackets, so that a REPL should read more before evaluating it.
+pub fn is_incomplete(source: &str) -> bool {
+    let error = Rc::new(RefCell::new(ErrorReporter::default()));
+    let mut depth = 0;
+    for token in source.chars().tokens(error.clone()) {
+        match token.token_type {
+            TokenType::LeftParen | TokenType::LeftBrace | TokenType::LeftBracket => depth += 1,
+            TokenType::RightParen | TokenType::RightBrace | TokenType::RightBracket => {
+                depth -= 1;
+            }
+            _ => {}
+        }
+    }
+    let unterminated_string = error
+        .borrow()
+        .diagnostics()
+        .iter()
+        .any(|diagnostic| diagnostic.message == "Unterminated string");
+    depth > 0 || unterminated_string
+}
+
+fn echo_expression(statement: Statement) -> Statement {
+    match statement {
+        Statement::Expression { expr }
+            if !matches!(
+                expr,
+                Expr::Assign { .. } | Expr::Set { .. } | Expr::SetIndex { .. }
+            ) =>
+        {
+            Statement::Print { expr }
+        }
+        statement => statement,
+    }
+}
+
+fn global_token(name: &str) -> Token {
+    Token::new(TokenType::Identifier, name.to_string(), 0, 0)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{Backend, EvalError, List, LiteralValue, Lox, Phase, Severity};
+
+    /// Runs the code with both backends, and checks that they produce the same output
+    pub(crate) fn run(input: &str) -> String {
+        let output = run_with_backend(input, Backend::TreeWalker);
+        assert_eq!(run_with_backend(input, Backend::Bytecode), output);
+        output
+    }
+
+    /// A session with each backend, for tests that do more than run code once
+    pub(crate) fn sessions() -> [Lox<Vec<u8>>; 2] {
+        [Backend::TreeWalker, Backend::Bytecode]
+            .map(|backend| Lox::with_backend(Vec::new(), backend))
+    }
+
+    fn run_with_backend(input: &str, backend: Backend) -> String {
+        let mut lox = Lox::with_backend(Vec::new(), backend);
+        let result = lox.eval(input);
+        let mut output = String::from_utf8(lox.into_output()).unwrap();
+        if let Err(error @ EvalError::Runtime(_)) = result {
+            output.push_str(&error.to_string());
+        }
+        output
+    }
+
+    #[test]
+    fn print_hello_world() {
+        assert_eq!(run("print \"Hello World!\";"), "Hello World!\n");
+    }
+
+    #[test]
+    fn print_expression() {
+        assert_eq!(run("print 1+2*3-(2+4)/3;"), "5\n");
+    }
+
+    #[test]
+    fn print_string_expression() {
+        assert_eq!(run("print \"Hello \"+\"World!\";"), "Hello World!\n");
+    }
+
+    #[test]
+    fn and_operator() {
+        assert_eq!(
+            run("
+                print false and false;
+                print false and true;
+                print true and false;
+                print true and true;
+                "),
+            "false\nfalse\nfalse\ntrue\n"
+        );
+    }
+
+    #[test]
+    fn or_operator() {
+        assert_eq!(
+            run("
+                print false or false;
+                print false or true;
+                print true or false;
+                print true or true;
+                "),
+            "false\ntrue\ntrue\ntrue\n"
+        );
+    }
+
+    #[test]
+    fn variable() {
+        assert_eq!(run("var a = 3; print a;"), "3\n");
+    }
+
+    #[test]
+    fn assignment() {
+        assert_eq!(
+            run("
+                var a = 1;
+                a = 2;
+                a = 3;
+                print a;
+                "),
+            "3\n"
+        );
+    }
+
+    #[test]
+    fn block() {
+        assert_eq!(
+            run("
+                var b = 1;
+                print b;
+                {
+                    var b = 2;
+                    print b;
+                }
+                "),
+            "1\n2\n"
+        );
+    }
+
+    #[test]
+    fn if_block() {
+        assert_eq!(
+            run("
+                var b = 1;
+                if (b == 1)
+                    print \"Yes\";
+                if (b < 1) {
+                    print \"No\";
+                } else {
+                    print \"Yes\";
+                }
+                "),
+            "Yes\nYes\n"
+        );
+    }
+
+    #[test]
+    fn while_loop() {
+        assert_eq!(
+            run("
+                var b = 3;
+                while (b > 0) {
+                    print b;
+                    b = b - 1;
+                }
+                "),
+            "3\n2\n1\n"
+        );
+    }
+
+    #[test]
+    fn for_loop() {
+        assert_eq!(
+            run("
+                var a = 0;
+                var temp;
+                for (var b = 1; a < 100; b = temp + b) {
+                    print a;
+                    temp = a;
+                    a = b;
+                }
+                "),
+            "0\n1\n1\n2\n3\n5\n8\n13\n21\n34\n55\n89\n"
+        );
+    }
+
+    #[test]
+    fn while_as_for_loop() {
+        assert_eq!(
+            run("
+                var a = 0;
+                var temp;
+                var b = 1;
+                while (a < 100) {
+                    print a;
+                    temp = a;
+                    a = b;
+                    b = temp + b;
+                }
+                "),
+            "0\n1\n1\n2\n3\n5\n8\n13\n21\n34\n55\n89\n"
+        );
+    }
+
+    #[test]
+    fn clock() {
+        run_with_backend("print clock();", Backend::TreeWalker);
+        run_with_backend("print clock();", Backend::Bytecode);
+    }
+
+    #[test]
+    fn func() {
+        assert_eq!(
+            run("
+                fun foo() { 
+                    print \"foo\"; 
+                    return 5;
+                }
+                print foo();
+                "),
+            "foo\n5\n"
+        );
+    }
+
+    #[test]
+    fn fib() {
+        assert_eq!(
+            run("
+                fun fib(n) {
+                    if (n <= 1) return n;
+                    return fib(n-2) + fib(n-1);
+                }
+                for (var i = 0; i < 20; i = i + 1) {
+                    print fib(i);
+                }
+            "),
+            "0\n1\n1\n2\n3\n5\n8\n13\n21\n34\n55\n89\n144\n233\n377\n610\n987\n1597\n2584\n4181\n"
+        );
+    }
+
+    #[test]
+    fn simple_loop() {
+        assert_eq!(
+            run("
+                for (var i = 0; i < 10; i = i + 1) {
+                    print i;
+                }
+            "),
+            "0\n1\n2\n3\n4\n5\n6\n7\n8\n9\n"
+        );
+    }
+
+    #[test]
+    fn class() {
+        assert_eq!(
+            run("
+                class Foo {}
+                print Foo;
+                print Foo();
+            "),
+            "Foo\nFoo instance\n"
+        );
+    }
+
+    #[test]
+    fn fields() {
+        assert_eq!(
+            run("
+                class Point {}
+                var p = Point();
+                p.x = 1;
+                p.y = p.x + 1;
+                print p.x;
+                print p.y;
+            "),
+            "1\n2\n"
+        );
+    }
+
+    #[test]
+    fn methods_and_this() {
+        assert_eq!(
+            run("
+                class Cake {
+                    taste() {
+                        print \"The \" + this.flavor + \" cake is delicious!\";
+                    }
+                }
+                var cake = Cake();
+                cake.flavor = \"chocolate\";
+                var taste = cake.taste;
+                taste();
+            "),
+            "The chocolate cake is delicious!\n"
+        );
+    }
+
+    #[test]
+    fn initializer() {
+        assert_eq!(
+            run("
+                class Counter {
+                    init(start) {
+                        this.count = start;
+                    }
+                    increment() {
+                        this.count = this.count + 1;
+                        return this;
+                    }
+                }
+                var counter = Counter(5);
+                print counter.increment().increment().count;
+                print counter.init(1) == counter;
+                print counter.count;
+            "),
+            "7\ntrue\n1\n"
+        );
+    }
+
+    #[test]
+    fn this_outside_class() {
+        for mut lox in sessions() {
+            assert!(matches!(
+                lox.eval("print 1;\nprint this;"),
+                Err(EvalError::Static)
+            ));
+            assert_eq!(lox.output(), b"");
+            let found: Vec<_> = lox
+                .diagnostics()
+                .iter()
+                .map(|d| (d.severity, d.phase, d.message.as_str(), d.line, d.column))
+                .collect();
+            assert_eq!(
+                found,
+                [(
+                    Severity::Error,
+                    Phase::Resolve,
+                    "Can't use 'this' outside of a class",
+                    2,
+                    7
+                )]
+            );
+        }
+    }
+
+    #[test]
+    fn inheritance() {
+        assert_eq!(
+            run("
+                class Doughnut {
+                    cook() {
+                        print \"Fry until golden brown.\";
+                    }
+                }
+                class BostonCream < Doughnut {}
+                BostonCream().cook();
+            "),
+            "Fry until golden brown.\n"
+        );
+    }
+
+    #[test]
+    fn super_call() {
+        assert_eq!(
+            run("
+                class A {
+                    init(name) {
+                        this.name = name;
+                    }
+                    method() {
+                        print \"A method \" + this.name;
+                    }
+                }
+                class B < A {
+                    init(name) {
+                        super.init(name + \"!\");
+                    }
+                    method() {
+                        print \"B method\";
+                    }
+                    test() {
+                        super.method();
+                    }
+                }
+                class C < B {}
+                C(\"c\").test();
+            "),
+            "A method c!\n"
+        );
+    }
+
+    #[test]
+    fn superclass_must_be_a_class() {
+        assert_eq!(
+            run("
+                var NotAClass = \"I am totally not a class\";
+                class Subclass < NotAClass {}
+                print \"unreachable\";
+            "),
+            "Runtime error: Superclass must be a class\n[line 3] in script"
+        );
+    }
+
+    #[test]
+    fn closure_counter() {
+        assert_eq!(
+            run("
+                fun makeCounter() {
+                    var i = 0;
+                    fun count() {
+                        i = i + 1;
+                        print i;
+                    }
+                    return count;
+                }
+                var counter = makeCounter();
+                counter();
+                counter();
+                var other = makeCounter();
+                other();
+            "),
+            "1\n2\n1\n"
+        );
+    }
+
+    #[test]
+    fn closure_does_not_see_caller_locals() {
+        assert_eq!(
+            run("
+                var a = \"global\";
+                fun showA() {
+                    print a;
+                }
+                fun caller() {
+                    var a = \"caller\";
+                    showA();
+                }
+                caller();
+            "),
+            "global\n"
+        );
+    }
+
+    #[test]
+    fn closure_is_static() {
+        assert_eq!(
+            run("
+                var a = \"global\";
+                {
+                    fun showA() {
+                        print a;
+                    }
+                    showA();
+                    var a = \"block\";
+                    showA();
+                }
+            "),
+            "global\nglobal\n"
+        );
+    }
+
+    #[test]
+    fn method_closure_captures_this() {
+        assert_eq!(
+            run("
+                class Thing {
+                    getCallback() {
+                        fun localFunction() {
+                            print this;
+                        }
+                        return localFunction;
+                    }
+                }
+                var callback = Thing().getCallback();
+                callback();
+            "),
+            "Thing instance\n"
+        );
+    }
+
+    #[test]
+    fn shadowed_variable() {
+        assert_eq!(
+            run("
+                {
+                    var a = 1;
+                    {
+                        var a = 2;
+                        print a;
+                    }
+                    print a;
+                }
+            "),
+            "2\n1\n"
+        );
+    }
+
+    #[test]
+    fn assignment_binds_to_resolved_scope() {
+        assert_eq!(
+            run("
+                var a = \"global\";
+                {
+                    fun assign() {
+                        a = \"assigned\";
+                        print a;
+                    }
+                    var a = \"block\";
+                    assign();
+                    print a;
+                }
+                print a;
+            "),
+            "assigned\nblock\nassigned\n"
+        );
+    }
+
+    #[test]
+    fn assignment_to_enclosing_local() {
+        assert_eq!(
+            run("
+                {
+                    var a = 1;
+                    {
+                        var b = 2;
+                        a = a + b;
+                    }
+                    print a;
+                }
+            "),
+            "3\n"
+        );
+    }
+
+    #[test]
+    fn truthiness_in_if() {
+        assert_eq!(
+            run("
+                if (1) print \"number\";
+                if (0) print \"zero\";
+                if (\"x\") print \"string\";
+                if (\"\") print \"empty string\";
+                if (clock) print \"function\";
+                if (nil) print \"nil\"; else print \"not nil\";
+                if (false) print \"false\"; else print \"not false\";
+                print !nil;
+                print !0;
+            "),
+            "number\nzero\nstring\nempty string\nfunction\nnot nil\nnot false\ntrue\nfalse\n"
+        );
+    }
+
+    #[test]
+    fn truthiness_in_while() {
+        assert_eq!(
+            run("
+                class Node {
+                    init(value, next) {
+                        this.value = value;
+                        this.next = next;
+                    }
+                }
+                var node = Node(1, Node(2, Node(3, nil)));
+                while (node) {
+                    print node.value;
+                    node = node.next;
+                }
+            "),
+            "1\n2\n3\n"
+        );
+    }
+
+    #[test]
+    fn truthiness_in_for() {
+        assert_eq!(
+            run("
+                for (var s = \"ab\"; s; s = nil) {
+                    print s;
+                }
+            "),
+            "ab\n"
+        );
+    }
+
+    #[test]
+    fn logical_operators_return_operand() {
+        assert_eq!(
+            run("
+                var input;
+                var name = input or \"default\";
+                print name;
+                print \"hi\" or 2;
+                print nil or \"yes\";
+                print 1 and 2;
+                print nil and \"no\";
+                print false or nil;
+            "),
+            "default\nhi\nyes\n2\nnil\nnil\n"
+        );
+    }
+
+    #[test]
+    fn logical_operators_short_circuit() {
+        assert_eq!(
+            run("
+                fun effect(value) {
+                    print \"evaluated\";
+                    return value;
+                }
+                print 1 or effect(2);
+                print nil and effect(2);
+                print nil or effect(3);
+            "),
+            "1\nnil\nevaluated\n3\n"
+        );
+    }
+
+    #[test]
+    fn globals_persist_between_evals() {
+        let mut lox = Lox::with_output(Vec::new());
+        lox.eval("var a = 1;").unwrap();
+        lox.eval("fun double(x) { return x * 2; }").unwrap();
+        lox.eval("print double(a);").unwrap();
+        assert_eq!(lox.output(), b"2\n");
+    }
+
+    #[test]
+    fn closures_between_evals() {
+        for mut lox in sessions() {
+            lox.eval(
+                "fun make(a) { var b = 2; { var c = 3; fun sum() { return a + b + c; } return sum; } }",
+            )
+            .unwrap();
+            lox.eval("var sum = make(1);").unwrap();
+            lox.eval("{ var shadow = 10; print sum() + shadow; }")
+                .unwrap();
+            assert_eq!(lox.output(), b"16\n");
+        }
+    }
+
+    #[test]
+    fn closures_survive_runtime_errors() {
+        for mut lox in sessions() {
+            let source = "
+                var g;
+                fun f() {
+                    var x = \"captured\";
+                    fun h() { return x; }
+                    g = h;
+                    nil + 1;
+                }
+                f();
+                ";
+            assert!(lox.eval(source).is_err());
+            lox.eval("print g();").unwrap();
+            assert_eq!(lox.output(), b"captured\n");
+        }
+    }
+
+    #[test]
+    fn redefine_globals_between_evals() {
+        for mut lox in sessions() {
+            lox.eval("var a = 1; fun f() { return a; }").unwrap();
+            lox.eval("var a = 2; fun f() { return -a; }").unwrap();
+            lox.eval("print f();").unwrap();
+            assert_eq!(lox.output(), b"-2\n");
+        }
+    }
+
+    #[test]
+    fn access_globals() {
+        let mut lox = Lox::with_output(Vec::new());
+        lox.set_global("name", LiteralValue::String("Lox".into()))
+            .unwrap();
+        lox.eval("var greeting = \"Hello \" + name;").unwrap();
+        assert_eq!(
+            lox.global("greeting"),
+            Some(LiteralValue::String("Hello Lox".into()))
+        );
+        assert_eq!(lox.global("missing"), None);
+    }
+
+    #[test]
+    fn strings_are_shared() {
+        let mut lox = Lox::with_output(Vec::new());
+        lox.eval("var a = \"shared\"; var b = a; fun f() { return b; } var c = f();")
+            .unwrap();
+        let (Some(LiteralValue::String(a)), Some(LiteralValue::String(c))) =
+            (lox.global("a"), lox.global("c"))
+        else {
+            panic!("Expected strings");
+        };
+        assert!(std::rc::Rc::ptr_eq(&a, &c));
+    }
+
+    #[test]
+    fn eval_errors() {
+        let mut lox = Lox::with_output(Vec::new());
+        assert!(matches!(lox.eval("print 1 +;"), Err(EvalError::Static)));
+        assert!(matches!(
+            lox.eval("print 1 / 0;"),
+            Err(EvalError::Runtime(error)) if error.message == "Division by 0"
+        ));
+    }
+
+    #[test]
+    fn register_fn() {
+        let mut lox = Lox::with_output(Vec::new());
+        lox.register_fn("add", |a: f64, b: f64| a + b);
+        lox.register_fn("greet", |name: String| format!("Hello {name}!"));
+        lox.register_fn("nothing", || ());
+        lox.eval("print add(1, 2);").unwrap();
+        lox.eval("print greet(\"Lox\");").unwrap();
+        lox.eval("print nothing();").unwrap();
+        assert_eq!(lox.output(), b"3\nHello Lox!\nnil\n");
+    }
+
+    #[test]
+    fn register_fn_argument_errors() {
+        let mut lox = Lox::with_output(Vec::new());
+        lox.register_fn("add", |a: f64, b: f64| a + b);
+        assert!(matches!(
+            lox.eval("add(1, \"two\");"),
+            Err(EvalError::Runtime(error))
+                if error.message == "Invalid argument 2 to add(): two is not a number"
+        ));
+        lox.register_fn("not", |value: bool| !value);
+        assert!(matches!(
+            lox.eval("not(\"abc\");"),
+            Err(EvalError::Runtime(error))
+                if error.message == "Invalid argument 1 to not(): abc is not a boolean"
+        ));
+        assert!(lox.eval("not(nil);").is_err());
+        lox.eval("print not(false);").unwrap();
+        assert_eq!(lox.output(), b"true\n");
+        assert!(matches!(
+            lox.eval("add(1);"),
+            Err(EvalError::Runtime(error))
+                if error.message == "Wrong number of arguments to function. Got 1 but function requires 2"
+        ));
+    }
+
+    #[test]
+    fn register_fallible_fn() {
+        let mut lox = Lox::with_output(Vec::new());
+        lox.register_fn("sqrt", |x: f64| {
+            if x < 0.0 {
+                Err(crate::Error {
+                    token: None,
+                    span: None,
+                    message: "Negative number".into(),
+                    trace: Vec::new(),
+                })
+            } else {
+                Ok(x.sqrt())
+            }
+        });
+        lox.eval("print sqrt(4);").unwrap();
+        assert_eq!(lox.output(), b"2\n");
+        assert!(matches!(
+            lox.eval("sqrt(-1);"),
+            Err(EvalError::Runtime(error)) if error.message == "Negative number"
+        ));
+    }
+
+    #[test]
+    fn closures_share_captured_variable() {
+        assert_eq!(
+            run("
+                var get;
+                var set;
+                {
+                    var a = \"initial\";
+                    fun getA() { return a; }
+                    fun setA(value) { a = value; }
+                    get = getA;
+                    set = setA;
+                }
+                set(\"updated\");
+                print get();
+            "),
+            "updated\n"
+        );
+    }
+
+    #[test]
+    fn nested_closures() {
+        assert_eq!(
+            run("
+                fun outer() {
+                    var x = \"x\";
+                    fun middle() {
+                        var y = \"y\";
+                        fun inner() {
+                            print x + y;
+                        }
+                        return inner;
+                    }
+                    return middle;
+                }
+                outer()()();
+            "),
+            "xy\n"
+        );
+    }
+
+    #[test]
+    fn closures_in_loop() {
+        assert_eq!(
+            run("
+                var first;
+                var second;
+                for (var i = 1; i <= 2; i = i + 1) {
+                    var j = i;
+                    fun show() { print j; }
+                    if (i == 1) first = show; else second = show;
+                }
+                first();
+                second();
+            "),
+            "1\n2\n"
+        );
+    }
+
+    #[test]
+    fn field_holding_function_is_callable() {
+        assert_eq!(
+            run("
+                fun hello() { print \"hello\"; }
+                class Box {}
+                var box = Box();
+                box.function = hello;
+                box.function();
+            "),
+            "hello\n"
+        );
+    }
+
+    #[test]
+    fn stack_overflow() {
+        let output = run("fun recurse() { recurse(); } recurse();");
+        assert!(output.starts_with("Runtime error: Stack overflow\n[line 1] in recurse()\n"));
+        assert!(output.ends_with("\n[line 1] in script"));
+    }
+
+    #[test]
+    fn deep_recursion() {
+        let output = run(
+            "fun count(n) { if (n == 0) return 0; return 1 + count(n - 1); }
+            print count(100);
+            print count(1022);
+            print count(1023);",
+        );
+        assert!(output.starts_with("100\n1022\nRuntime error: Stack overflow\n"));
+    }
+
+    #[test]
+    fn bytecode_globals_and_native_functions() {
+        let mut lox = Lox::with_backend(Vec::new(), Backend::Bytecode);
+        lox.register_fn("add", |a: f64, b: f64| a + b);
+        lox.set_global("a", LiteralValue::Number(1.0)).unwrap();
+        lox.eval("var b = add(a, 2);").unwrap();
+        lox.eval("fun f() {}").unwrap();
+        assert_eq!(lox.global("b"), Some(LiteralValue::Number(3.0)));
+        assert_eq!(lox.global("f"), None);
+    }
+
+    #[test]
+    fn tree_walker_instances_are_rejected_by_bytecode() {
+        let mut tree_walker = Lox::with_backend(Vec::new(), Backend::TreeWalker);
+        tree_walker.eval("class A {} var a = A();").unwrap();
+        let instance = tree_walker.global("a").unwrap();
+
+        let mut lox = Lox::with_backend(Vec::new(), Backend::Bytecode);
+        let error = lox.set_global("a", instance.clone()).unwrap_err();
+        assert_eq!(error.message, "A instance can't be used by the bytecode VM");
+        let list = LiteralValue::List(List::new(vec![instance.clone()]));
+        assert!(lox.set_global("list", list).is_err());
+
+        lox.register_fn("make", move || instance.clone());
+        assert!(matches!(
+            lox.eval("make();"),
+            Err(EvalError::Runtime(error))
+                if error.message == "A instance can't be used by the bytecode VM"
+        ));
+        lox.register_fn("ignore", |_: LiteralValue| ());
+        assert!(matches!(
+            lox.eval("class B {} ignore(B());"),
+            Err(EvalError::Runtime(error))
+                if error.message == "B instance can't be passed to a native function"
+        ));
+    }
+
+    #[test]
+    fn warnings() {
+        let source = "
+            fun f(a, b) {
+              var unused;
+              var assigned;
+              assigned = 1;
+              var allowed; // lox-allow(unused)
+              return a;
+              print a;
+            }
+            print f(1, 2);
+        ";
+        let mut lox = Lox::with_output(Vec::new());
+        lox.eval(source).unwrap();
+        assert_eq!(lox.output(), b"1\n");
+        let found: Vec<_> = lox
+            .diagnostics()
+            .iter()
+            .map(|d| (d.severity, d.phase, d.message.as_str(), d.line))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (Severity::Warning, Phase::Resolve, "Unreachable code", 8),
+                (Severity::Warning, Phase::Resolve, "Unused parameter 'b'", 2),
+                (
+                    Severity::Warning,
+                    Phase::Resolve,
+                    "Unused variable 'unused'",
+                    3
+                ),
+                (
+                    Severity::Warning,
+                    Phase::Resolve,
+                    "Unused variable 'assigned'",
+                    4
+                ),
+            ]
+        );
+
+        lox.set_deny_warnings(true);
+        assert!(matches!(lox.eval(source), Err(EvalError::Static)));
+        assert_eq!(lox.diagnostics().len(), 4);
+        assert!(lox
+            .diagnostics()
+            .iter()
+            .all(|d| d.severity == Severity::Error));
+    }
+
+    #[test]
+    fn unreachable_code_after_if_else() {
+        let mut lox = Lox::with_output(Vec::new());
+        lox.eval("fun f(x) { if (x) return 1; print x; }").unwrap();
+        assert!(lox.diagnostics().is_empty());
+        lox.eval("fun g(x) { if (x) return 1; else { return 2; } print x; }")
+            .unwrap();
+        assert_eq!(lox.diagnostics()[0].message, "Unreachable code");
+        assert_eq!(lox.diagnostics()[0].column, 54);
+    }
+
+    #[test]
+    fn no_resolver_diagnostics_after_syntax_errors() {
+        let mut lox = Lox::with_output(Vec::new());
+        assert!(matches!(
+            lox.eval("fun f( { return 1; }"),
+            Err(EvalError::Static)
+        ));
+        let messages: Vec<_> = lox
+            .diagnostics()
+            .iter()
+            .map(|diagnostic| (diagnostic.phase, diagnostic.message.as_str()))
+            .collect();
+        assert_eq!(
+            messages,
+            [
+                (Phase::Parse, "Expected parameter name"),
+                (Phase::Parse, "Expected expression")
+            ]
+        );
+    }
+
+    #[test]
+    fn collect_diagnostics() {
+        let mut lox = Lox::with_output(Vec::new());
+        assert!(lox.eval("print @;\n{ var a = a; }").is_err());
+        let found: Vec<_> = lox
+            .diagnostics()
+            .iter()
+            .map(|d| (d.severity, d.phase, d.message.as_str(), d.line, d.column))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (Severity::Error, Phase::Scan, "Unexpected character", 1, 7),
+                (Severity::Error, Phase::Parse, "Expected expression", 1, 8),
+            ]
+        );
+
+        assert!(lox.eval("{ var a = a; }").is_err());
+        let diagnostic = &lox.diagnostics()[0];
+        assert_eq!(diagnostic.phase, Phase::Resolve);
+        assert_eq!(diagnostic.token.as_ref().unwrap().lexeme, "a");
+
+        assert!(lox.eval("print 1;\nprint -nil;").is_err());
+        let diagnostic = &lox.diagnostics()[0];
+        assert_eq!(
+            (diagnostic.phase, diagnostic.message.as_str()),
+            (Phase::Runtime, "nil is not a number")
+        );
+
+        lox.eval("print 2;").unwrap();
+        assert!(lox.diagnostics().is_empty());
+    }
+
+    #[test]
+    fn runtime_error_location_on_both_backends() {
+        for source in [
+            "print -nil;",
+            "print (nil) + 1;",
+            "print 1 + nil;",
+            "print 1 <\n  nil;",
+            "print 1 / 0;",
+            "print undefined;",
+            "undefined = 1;",
+            "var x = 1;\nx();",
+            "fun f(a) {}\nf();",
+            "class A {}\nA().m();",
+            "class A { m(a) {} }\nA().m();",
+            "class A {}\nvar a = A();\na.f = 1;\na.f();",
+            "class A {}\nprint A().field;",
+            "var n = nil;\nn.m();",
+            "var n = nil;\nn.field = 1;",
+            "class A {}\nclass B < A { m() { super.m(); } }\nB().m();",
+            "class A {}\nclass B < A { m() { return super.m; } }\nB().m();",
+            "var NotClass = 1;\nclass B < NotClass {}",
+            "var list = [1];\nprint list[2];",
+            "var list = [1];\nlist[2] = 1;",
+            "push(nil, 1);",
+            "fun f() { f(); }\nf();",
+        ] {
+            let diagnostics = sessions().map(|mut lox| {
+                assert!(lox.eval(source).is_err(), "{source}");
+                let diagnostic = &lox.diagnostics()[0];
+                assert!(diagnostic.column > 0, "{source}");
+                (diagnostic.to_json("x.lox"), lox.render(diagnostic))
+            });
+            assert_eq!(diagnostics[0], diagnostics[1], "{source}");
+        }
+    }
+
+    #[test]
+    fn runtime_error_stack_trace() {
+        assert_eq!(
+            run("
+                fun inner(x) {
+                    return x + nil;
+                }
+                fun outer() {
+                    return inner(1);
+                }
+                print \"before\";
+                outer();
+            "),
+            "before\nRuntime error: nil is not a number\n\
+             [line 3] in inner()\n[line 6] in outer()\n[line 9] in script"
+        );
+    }
+
+    #[test]
+    fn runtime_error_stack_trace_in_methods() {
+        assert_eq!(
+            run("
+                class Point {
+                    init(x) {
+                        this.x = -x;
+                    }
+                    norm() {
+                        return this.y;
+                    }
+                }
+                var p = Point(1);
+                p.norm();
+            "),
+            "Runtime error: Undefined property 'y'\n[line 7] in norm()\n[line 11] in script"
+        );
+        assert_eq!(
+            run("
+                class Point {
+                    init(x) {
+                        this.x = -x;
+                    }
+                }
+                Point(\"one\");
+            "),
+            "Runtime error: one is not a number\n[line 4] in init()\n[line 7] in script"
+        );
+    }
+
+    #[test]
+    fn runtime_error_location() {
+        let mut lox = Lox::with_output(Vec::new());
+        lox.register_fn("half", |x: f64| x / 2.0);
+        for (source, line, column) in [
+            ("print -nil;", 1, 7),
+            ("print 1 <\n  nil;", 1, 9),
+            ("print undefined;", 1, 7),
+            ("undefined = 1;", 1, 1),
+            ("var x = 1;\nprint half(\"two\");", 2, 17),
+        ] {
+            assert!(lox.eval(source).is_err());
+            let diagnostic = &lox.diagnostics()[0];
+            assert_eq!(
+                (diagnostic.line, diagnostic.column),
+                (line, column),
+                "{source}"
+            );
+            assert_eq!(diagnostic.trace[0].line, line);
+        }
+    }
+
+    #[test]
+    fn incomplete_input() {
+        assert!(crate::is_incomplete("fun f() {"));
+        assert!(crate::is_incomplete("print (1 +"));
+        assert!(crate::is_incomplete("var list = [1,"));
+        assert!(crate::is_incomplete("print \"one\ntwo"));
+        assert!(!crate::is_incomplete("fun f() {}"));
+        assert!(!crate::is_incomplete("print 1 +"));
+        assert!(!crate::is_incomplete("}"));
+    }
+
+    #[test]
+    fn echo_expressions() {
+        for mut lox in sessions() {
+            lox.set_echo_expressions(true);
+            lox.eval("var a = 1; a = 2; 1 + a; \"a\"; { a; }").unwrap();
+            assert_eq!(String::from_utf8(lox.into_output()).unwrap(), "3\na\n");
+        }
+    }
+
+    #[test]
+    fn reset() {
+        for mut lox in sessions() {
+            let builtins = lox.globals().len();
+            lox.eval("var a = 1;").unwrap();
+            assert_eq!(lox.globals().len(), builtins + 1);
+            lox.reset();
+            assert!(lox.eval("print a;").is_err());
+            lox.eval("print clock() > 0;").unwrap();
+            assert_eq!(lox.globals().len(), builtins);
+        }
+    }
+}
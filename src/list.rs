@@ -0,0 +1,247 @@
+use std::cell::{Ref, RefCell, RefMut};
+use std::fmt::{Debug, Display, Formatter};
+use std::rc::Rc;
+
+use crate::error_reporter::{Error, Result};
+
+/// A mutable list of values. Copies of a list refer to the same elements, and lists are
+/// only equal to themselves.
+pub struct List<T>(Rc<RefCell<Vec<T>>>);
+
+/// The values that both backends store in lists, so that indexing and the list functions
+/// can be shared.
+pub trait Element: Clone + Display + Sized {
+    fn nil() -> Self;
+    fn number(value: f64) -> Self;
+    fn list(list: List<Self>) -> Self;
+    fn as_number(&self) -> Option<f64>;
+    fn as_list(&self) -> Option<&List<Self>>;
+}
+
+impl<T> List<T> {
+    pub fn new(elements: Vec<T>) -> Self {
+        Self(Rc::new(RefCell::new(elements)))
+    }
+
+    pub fn borrow(&self) -> Ref<'_, Vec<T>> {
+        self.0.borrow()
+    }
+
+    pub fn borrow_mut(&self) -> RefMut<'_, Vec<T>> {
+        self.0.borrow_mut()
+    }
+}
+
+impl<T> Clone for List<T> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<T> PartialEq for List<T> {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl<T: Display> Display for List<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        // Borrowing the elements mutably makes a list that contains itself fail to borrow
+        // them again, so it is shown as [...] instead of recursing forever
+        let Ok(elements) = self.0.try_borrow_mut() else {
+            return write!(f, "[...]");
+        };
+        write!(f, "[")?;
+        for (i, element) in elements.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{element}")?;
+        }
+        write!(f, "]")
+    }
+}
+
+impl<T: Display> Debug for List<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{self}")
+    }
+}
+
+/// Evaluates `object[index]`
+pub fn get<T: Element>(object: &T, index: &T) -> Result<T> {
+    let list = indexed_list(object)?;
+    let elements = list.borrow();
+    let index = position(index, elements.len())?;
+    Ok(elements[index].clone())
+}
+
+/// Evaluates `object[index] = value`
+pub fn set<T: Element>(object: &T, index: &T, value: T) -> Result<()> {
+    let list = indexed_list(object)?;
+    let mut elements = list.borrow_mut();
+    let index = position(index, elements.len())?;
+    elements[index] = value;
+    Ok(())
+}
+
+fn indexed_list<T: Element>(object: &T) -> Result<&List<T>> {
+    object
+        .as_list()
+        .ok_or_else(|| error(format!("Only lists can be indexed, not {object}")))
+}
+
+/// Converts an index into a position in a list of the given length
+fn position<T: Element>(index: &T, len: usize) -> Result<usize> {
+    position_up_to(index, len).and_then(|position| {
+        if position < len {
+            Ok(position)
+        } else {
+            Err(out_of_bounds(index, len))
+        }
+    })
+}
+
+/// Converts an index into a position in a list of the given length, allowing the position
+/// just after the last element
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn position_up_to<T: Element>(index: &T, len: usize) -> Result<usize> {
+    let Some(number) = index.as_number() else {
+        return Err(error(format!("List index {index} is not a number")));
+    };
+    if number.fract() != 0.0 {
+        return Err(error(format!("List index {number} is not a whole number")));
+    }
+    if number < 0.0 || number > len as f64 {
+        return Err(out_of_bounds(index, len));
+    }
+    Ok(number as usize)
+}
+
+fn out_of_bounds<T: Element>(index: &T, len: usize) -> Error {
+    error(format!(
+        "Index {index} is out of bounds for a list of length {len}"
+    ))
+}
+
+/// The native functions on lists. They are implemented here for both backends, as the
+/// functions registered with [`crate::Lox::register_fn`] can't change a list of the VM.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ListFunction {
+    /// `len(list)`: the number of elements
+    Len,
+    /// `push(list, value)`: appends a value
+    Push,
+    /// `pop(list)`: removes the last element and returns it
+    Pop,
+    /// `insert(list, index, value)`: inserts a value before the element at the index, or at
+    /// the end if the index is the length of the list
+    Insert,
+    /// `remove(list, index)`: removes the element at the index and returns it
+    Remove,
+    /// `slice(list, start, end)`: a new list with the elements from start up to, but not
+    /// including, end
+    Slice,
+}
+
+impl ListFunction {
+    pub const ALL: [ListFunction; 6] = [
+        ListFunction::Len,
+        ListFunction::Push,
+        ListFunction::Pop,
+        ListFunction::Insert,
+        ListFunction::Remove,
+        ListFunction::Slice,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ListFunction::Len => "len",
+            ListFunction::Push => "push",
+            ListFunction::Pop => "pop",
+            ListFunction::Insert => "insert",
+            ListFunction::Remove => "remove",
+            ListFunction::Slice => "slice",
+        }
+    }
+
+    pub fn arity(self) -> usize {
+        match self {
+            ListFunction::Len | ListFunction::Pop => 1,
+            ListFunction::Push | ListFunction::Remove => 2,
+            ListFunction::Insert | ListFunction::Slice => 3,
+        }
+    }
+
+    /// Calls the function with arguments whose number has been checked
+    #[allow(clippy::cast_precision_loss)]
+    pub fn call<T: Element>(self, arguments: &[T]) -> Result<T> {
+        let list = arguments[0]
+            .as_list()
+            .ok_or_else(|| self.invalid_argument(1, &format!("{} is not a list", arguments[0])))?;
+        let mut elements = list.borrow_mut();
+        let len = elements.len();
+        match self {
+            ListFunction::Len => Ok(T::number(len as f64)),
+            ListFunction::Push => {
+                elements.push(arguments[1].clone());
+                Ok(T::nil())
+            }
+            ListFunction::Pop => elements
+                .pop()
+                .ok_or_else(|| error("Can't pop from an empty list".into())),
+            ListFunction::Insert => {
+                let index = position_up_to(&arguments[1], len)?;
+                elements.insert(index, arguments[2].clone());
+                Ok(T::nil())
+            }
+            ListFunction::Remove => {
+                let index = position(&arguments[1], len)?;
+                Ok(elements.remove(index))
+            }
+            ListFunction::Slice => {
+                let start = position_up_to(&arguments[1], len)?;
+                let end = position_up_to(&arguments[2], len)?;
+                if start > end {
+                    return Err(error(format!("Slice start {start} is after its end {end}")));
+                }
+                Ok(T::list(List::new(elements[start..end].to_vec())))
+            }
+        }
+    }
+
+    fn invalid_argument(self, position: usize, message: &str) -> Error {
+        error(format!(
+            "Invalid argument {position} to {}(): {message}",
+            self.name()
+        ))
+    }
+}
+
+fn error(message: String) -> Error {
+    Error {
+        token: None,
+        span: None,
+        message,
+        trace: Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::test::sessions;
+    use crate::{List, LiteralValue};
+
+    #[test]
+    fn lists_between_evals_and_rust() {
+        for mut lox in sessions() {
+            let list = List::new(vec![LiteralValue::Number(1.0)]);
+            lox.set_global("list", LiteralValue::List(list)).unwrap();
+            lox.eval("push(list, \"two\"); var copy = slice(list, 0, 2);")
+                .unwrap();
+            lox.eval("copy[0] = nil; print list; print copy;").unwrap();
+            assert_eq!(lox.global("copy").unwrap().to_string(), "[nil, two]");
+            assert_eq!(lox.output(), b"[1, two]\n[nil, two]\n");
+        }
+    }
+}
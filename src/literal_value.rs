@@ -1,14 +1,23 @@
+use std::cell::RefCell;
 use std::fmt::{Display, Formatter};
+use std::rc::Rc;
 
 use crate::error_reporter;
+use crate::list::{Element, List};
 use crate::lox_callable::LoxCallable;
+use crate::lox_class::LoxClass;
+use crate::lox_instance::LoxInstance;
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum LiteralValue {
     Bool(bool),
-    String(String),
+    /// Strings are immutable, so copies of a value share the characters
+    String(Rc<str>),
     Number(f64),
     Function(LoxCallable),
+    Class(Rc<LoxClass>),
+    Instance(Rc<RefCell<LoxInstance>>),
+    List(List<LiteralValue>),
     Nil,
 }
 
@@ -19,6 +28,9 @@ impl Display for LiteralValue {
             LiteralValue::String(value) => write!(f, "{value}"),
             LiteralValue::Number(value) => write!(f, "{value}"),
             LiteralValue::Function(func) => write!(f, "{func}"),
+            LiteralValue::Class(class) => write!(f, "{class}"),
+            LiteralValue::Instance(instance) => write!(f, "{}", instance.borrow()),
+            LiteralValue::List(list) => write!(f, "{list}"),
             LiteralValue::Nil => write!(f, "nil"),
         }
     }
@@ -33,7 +45,9 @@ impl TryFrom<LiteralValue> for f64 {
         }
         Err(error_reporter::Error {
             token: None,
+            span: None,
             message: format!("{value} is not a number"),
+            trace: Vec::new(),
         })
     }
 }
@@ -43,25 +57,65 @@ impl TryFrom<LiteralValue> for String {
 
     fn try_from(value: LiteralValue) -> Result<Self, Self::Error> {
         if let LiteralValue::String(string) = value {
-            return Ok(string);
+            return Ok(string.to_string());
         }
         Err(error_reporter::Error {
             token: None,
+            span: None,
             message: format!("{value} is not a string"),
+            trace: Vec::new(),
         })
     }
 }
 
+impl LiteralValue {
+    /// Lox truthiness: `false` and `nil` are falsey, everything else is truthy.
+    pub fn is_truthy(&self) -> bool {
+        match self {
+            LiteralValue::Bool(value) => *value,
+            LiteralValue::String(_)
+            | LiteralValue::Number(_)
+            | LiteralValue::Function(_)
+            | LiteralValue::Class(_)
+            | LiteralValue::Instance(_)
+            | LiteralValue::List(_) => true,
+            LiteralValue::Nil => false,
+        }
+    }
+}
+
 impl TryFrom<LiteralValue> for bool {
     type Error = error_reporter::Error;
 
     fn try_from(value: LiteralValue) -> Result<Self, Self::Error> {
-        match value {
-            LiteralValue::Bool(value) => Ok(value),
-            LiteralValue::String(_) | LiteralValue::Number(_) | LiteralValue::Function(_) => {
-                Ok(true)
-            }
-            LiteralValue::Nil => Ok(false),
+        Ok(value.is_truthy())
+    }
+}
+
+impl Element for LiteralValue {
+    fn nil() -> Self {
+        LiteralValue::Nil
+    }
+
+    fn number(value: f64) -> Self {
+        LiteralValue::Number(value)
+    }
+
+    fn list(list: List<Self>) -> Self {
+        LiteralValue::List(list)
+    }
+
+    fn as_number(&self) -> Option<f64> {
+        match self {
+            LiteralValue::Number(number) => Some(*number),
+            _ => None,
+        }
+    }
+
+    fn as_list(&self) -> Option<&List<Self>> {
+        match self {
+            LiteralValue::List(list) => Some(list),
+            _ => None,
         }
     }
 }
@@ -10,32 +10,45 @@ use crate::literal_value::LiteralValue;
 use crate::statement::Statement;
 use crate::token::Token;
 
+/// The environment argument is the closure of the function when it has one, and the environment
+/// of the caller otherwise (which native functions are free to ignore).
 pub type LoxCallableFn =
     dyn Fn(Vec<LiteralValue>, &Rc<RefCell<Environment>>, &mut Interpreter) -> Result<LiteralValue>;
 
 #[derive(Clone)]
 pub struct LoxCallable {
+    name: String,
     func: Rc<LoxCallableFn>,
     num_arguments: usize,
+    closure: Option<Rc<RefCell<Environment>>>,
 }
 
 impl LoxCallable {
-    pub fn from_fn(func: Rc<LoxCallableFn>, num_arguments: usize) -> Self {
+    pub fn from_fn(name: &str, func: Rc<LoxCallableFn>, num_arguments: usize) -> Self {
         Self {
+            name: name.to_string(),
             func,
             num_arguments,
+            closure: None,
         }
     }
 
-    pub fn from_statement(params: Vec<Token>, body: Vec<Statement>) -> Self {
+    /// Creates a function that captures the environment it is declared in.
+    pub fn from_statement(
+        name: &str,
+        params: Rc<[Token]>,
+        body: Rc<[Statement]>,
+        closure: &Rc<RefCell<Environment>>,
+    ) -> Self {
         let num_arguments = params.len();
         Self {
-            func: Rc::new(move |args, env, interpreter| {
-                let environment = Rc::new(RefCell::new(Environment::from_parent(env)));
-                for (param, arg) in params.iter().zip(args.into_iter()) {
-                    (*environment).borrow_mut().define(param, arg)?;
+            name: name.to_string(),
+            func: Rc::new(move |args, closure, interpreter| {
+                let environment = Rc::new(RefCell::new(Environment::from_parent(closure)));
+                for (param, arg) in params.iter().zip(args) {
+                    (*environment).borrow_mut().define(&param.lexeme, arg);
                 }
-                for statement in &body {
+                for statement in body.iter() {
                     match statement.execute(&environment, interpreter) {
                         Ok(..) => {}
                         Err(ErrorOrReturn::Error(error)) => return Err(error),
@@ -45,6 +58,35 @@ impl LoxCallable {
                 Ok(LiteralValue::Nil)
             }),
             num_arguments,
+            closure: Some(closure.clone()),
+        }
+    }
+
+    /// Creates a method bound to an instance, with `this` defined in a scope between the
+    /// closure and the method body. Initializers always return `this`.
+    pub fn bind(&self, this: LiteralValue, is_initializer: bool) -> Self {
+        let closure = self
+            .closure
+            .as_ref()
+            .expect("Only functions declared in Lox can be bound");
+        let environment = Rc::new(RefCell::new(Environment::from_parent(closure)));
+        (*environment).borrow_mut().define("this", this.clone());
+        let func = if is_initializer {
+            let func = self.func.clone();
+            Rc::new(
+                move |args, closure: &Rc<RefCell<Environment>>, interpreter: &mut Interpreter| {
+                    func(args, closure, interpreter)?;
+                    Ok(this.clone())
+                },
+            )
+        } else {
+            self.func.clone()
+        };
+        Self {
+            name: self.name.clone(),
+            func,
+            num_arguments: self.num_arguments,
+            closure: Some(environment),
         }
     }
 
@@ -54,17 +96,37 @@ impl LoxCallable {
         environment: &Rc<RefCell<Environment>>,
         interpreter: &mut Interpreter,
     ) -> Result<LiteralValue> {
-        (self.func)(arguments, environment, interpreter)
+        (self.func)(
+            arguments,
+            self.closure.as_ref().unwrap_or(environment),
+            interpreter,
+        )
     }
 
     pub fn arity(&self) -> usize {
         self.num_arguments
     }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Native functions are implemented in Rust, and have no call frame of their own
+    pub fn is_native(&self) -> bool {
+        self.closure.is_none()
+    }
 }
 
+/// Functions are equal if they are copies of the same function. Declaring a function again
+/// or binding a method creates a different one.
 impl PartialEq for LoxCallable {
-    fn eq(&self, _other: &Self) -> bool {
-        false
+    fn eq(&self, other: &Self) -> bool {
+        let same_closure = match (&self.closure, &other.closure) {
+            (Some(a), Some(b)) => Rc::ptr_eq(a, b),
+            (None, None) => true,
+            _ => false,
+        };
+        Rc::ptr_eq(&self.func, &other.func) && same_closure
     }
 }
 
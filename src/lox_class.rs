@@ -0,0 +1,85 @@
+use std::cell::RefCell;
+use std::fmt::{Debug, Display, Formatter};
+use std::rc::Rc;
+
+use rustc_hash::FxHashMap;
+
+use crate::environment::Environment;
+use crate::error_reporter::Result;
+use crate::interpreter::Interpreter;
+use crate::literal_value::LiteralValue;
+use crate::lox_callable::LoxCallable;
+use crate::lox_instance::LoxInstance;
+
+pub struct LoxClass {
+    name: String,
+    superclass: Option<Rc<LoxClass>>,
+    methods: FxHashMap<String, LoxCallable>,
+}
+
+impl LoxClass {
+    pub fn new(
+        name: String,
+        superclass: Option<Rc<LoxClass>>,
+        methods: FxHashMap<String, LoxCallable>,
+    ) -> Self {
+        Self {
+            name,
+            superclass,
+            methods,
+        }
+    }
+
+    /// Finds a method in this class or the closest superclass that defines it.
+    pub fn find_method(&self, name: &str) -> Option<&LoxCallable> {
+        if let Some(method) = self.methods.get(name) {
+            Some(method)
+        } else if let Some(superclass) = &self.superclass {
+            superclass.find_method(name)
+        } else {
+            None
+        }
+    }
+
+    /// Looks up a method and binds `this` to the given instance.
+    pub fn bind_method(&self, name: &str, instance: LiteralValue) -> Option<LoxCallable> {
+        self.find_method(name)
+            .map(|method| method.bind(instance, name == "init"))
+    }
+
+    pub fn arity(&self) -> usize {
+        self.find_method("init").map_or(0, LoxCallable::arity)
+    }
+
+    /// Creates a new instance of the class and runs the initializer (if any) on it.
+    pub fn instantiate(
+        class: &Rc<Self>,
+        arguments: Vec<LiteralValue>,
+        environment: &Rc<RefCell<Environment>>,
+        interpreter: &mut Interpreter,
+    ) -> Result<LiteralValue> {
+        let instance = LiteralValue::Instance(Rc::new(RefCell::new(LoxInstance::new(class))));
+        if let Some(initializer) = class.bind_method("init", instance.clone()) {
+            initializer.call(arguments, environment, interpreter)?;
+        }
+        Ok(instance)
+    }
+}
+
+impl PartialEq for LoxClass {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl Display for LoxClass {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+impl Debug for LoxClass {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{self}")
+    }
+}
@@ -0,0 +1,66 @@
+use std::cell::RefCell;
+use std::fmt::{Debug, Display, Formatter};
+use std::rc::Rc;
+
+use rustc_hash::FxHashMap;
+
+use crate::error_reporter::{Error, Result};
+use crate::literal_value::LiteralValue;
+use crate::lox_class::LoxClass;
+use crate::token::Token;
+
+pub struct LoxInstance {
+    class: Rc<LoxClass>,
+    fields: FxHashMap<String, LiteralValue>,
+}
+
+impl LoxInstance {
+    pub fn new(class: &Rc<LoxClass>) -> Self {
+        Self {
+            class: class.clone(),
+            fields: FxHashMap::default(),
+        }
+    }
+
+    /// Gets a field, or a method bound to this instance if there is no field with that name.
+    pub fn get(instance: &Rc<RefCell<Self>>, name: &Token) -> Result<LiteralValue> {
+        let class = {
+            let instance = instance.borrow();
+            if let Some(value) = instance.fields.get(&name.lexeme) {
+                return Ok(value.clone());
+            }
+            instance.class.clone()
+        };
+        class
+            .bind_method(&name.lexeme, LiteralValue::Instance(instance.clone()))
+            .map(LiteralValue::Function)
+            .ok_or_else(|| Error {
+                token: Some(Box::new(name.clone())),
+                span: Some(name.span),
+                message: format!("Undefined property '{}'", name.lexeme),
+                trace: Vec::new(),
+            })
+    }
+
+    pub fn set(&mut self, name: &Token, value: LiteralValue) {
+        self.fields.insert(name.lexeme.clone(), value);
+    }
+}
+
+impl PartialEq for LoxInstance {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl Display for LoxInstance {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} instance", self.class)
+    }
+}
+
+impl Debug for LoxInstance {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{self}")
+    }
+}
@@ -0,0 +1,169 @@
+use std::rc::Rc;
+
+use crate::error_reporter::{Error, Result};
+use crate::literal_value::LiteralValue;
+use crate::lox_callable::LoxCallable;
+
+/// Conversion of a Lox value into an argument of a native function.
+pub trait FromLox: Sized {
+    fn from_lox(value: LiteralValue) -> Result<Self>;
+}
+
+/// Conversion of the return value of a native function into a Lox value.
+/// Returning an `Err` from a native function reports a runtime error.
+pub trait IntoLox {
+    fn into_lox(self) -> Result<LiteralValue>;
+}
+
+/// A Rust function or closure that can be called from Lox.
+/// This is implemented for functions taking up to eight arguments that implement [`FromLox`],
+/// and returning something that implements [`IntoLox`].
+pub trait NativeFunction<Args> {
+    fn arity(&self) -> usize;
+    fn call(&self, name: &str, arguments: Vec<LiteralValue>) -> Result<LiteralValue>;
+}
+
+/// Wraps a native function so that it can be stored in a Lox variable.
+pub fn native_function<Args>(
+    name: &str,
+    func: impl NativeFunction<Args> + 'static,
+) -> LiteralValue {
+    let arity = func.arity();
+    let function_name = name.to_string();
+    LiteralValue::Function(LoxCallable::from_fn(
+        name,
+        Rc::new(move |args, _env, _interpreter| func.call(&function_name, args)),
+        arity,
+    ))
+}
+
+impl FromLox for LiteralValue {
+    fn from_lox(value: LiteralValue) -> Result<Self> {
+        Ok(value)
+    }
+}
+
+impl FromLox for f64 {
+    fn from_lox(value: LiteralValue) -> Result<Self> {
+        value.try_into()
+    }
+}
+
+impl FromLox for String {
+    fn from_lox(value: LiteralValue) -> Result<Self> {
+        value.try_into()
+    }
+}
+
+/// Only `true` and `false` are accepted, rather than any value by its truthiness
+impl FromLox for bool {
+    fn from_lox(value: LiteralValue) -> Result<Self> {
+        if let LiteralValue::Bool(value) = value {
+            return Ok(value);
+        }
+        Err(Error {
+            token: None,
+            span: None,
+            message: format!("{value} is not a boolean"),
+            trace: Vec::new(),
+        })
+    }
+}
+
+impl IntoLox for LiteralValue {
+    fn into_lox(self) -> Result<LiteralValue> {
+        Ok(self)
+    }
+}
+
+impl IntoLox for f64 {
+    fn into_lox(self) -> Result<LiteralValue> {
+        Ok(LiteralValue::Number(self))
+    }
+}
+
+impl IntoLox for String {
+    fn into_lox(self) -> Result<LiteralValue> {
+        Ok(LiteralValue::String(self.into()))
+    }
+}
+
+impl IntoLox for &str {
+    fn into_lox(self) -> Result<LiteralValue> {
+        Ok(LiteralValue::String(self.into()))
+    }
+}
+
+impl IntoLox for bool {
+    fn into_lox(self) -> Result<LiteralValue> {
+        Ok(LiteralValue::Bool(self))
+    }
+}
+
+impl IntoLox for () {
+    fn into_lox(self) -> Result<LiteralValue> {
+        Ok(LiteralValue::Nil)
+    }
+}
+
+impl<T: IntoLox> IntoLox for Option<T> {
+    fn into_lox(self) -> Result<LiteralValue> {
+        self.map_or(Ok(LiteralValue::Nil), IntoLox::into_lox)
+    }
+}
+
+impl<T: IntoLox> IntoLox for Result<T> {
+    fn into_lox(self) -> Result<LiteralValue> {
+        self?.into_lox()
+    }
+}
+
+fn argument<T: FromLox>(function: &str, position: usize, value: LiteralValue) -> Result<T> {
+    T::from_lox(value).map_err(|error| Error {
+        token: None,
+        span: None,
+        message: format!(
+            "Invalid argument {position} to {function}(): {}",
+            error.message
+        ),
+        trace: Vec::new(),
+    })
+}
+
+macro_rules! impl_native_function {
+    ($arity:literal $(; $($position:literal $param:ident $arg:ident),+)?) => {
+        impl<Func, Ret $($(, $param)+)?> NativeFunction<($($($param,)+)?)> for Func
+        where
+            Func: Fn($($($param),+)?) -> Ret,
+            Ret: IntoLox,
+            $($($param: FromLox,)+)?
+        {
+            fn arity(&self) -> usize {
+                $arity
+            }
+
+            #[allow(unused_variables, unused_mut)]
+            fn call(&self, name: &str, arguments: Vec<LiteralValue>) -> Result<LiteralValue> {
+                let mut arguments = arguments.into_iter();
+                $($(
+                    let $arg: $param = argument(
+                        name,
+                        $position,
+                        arguments.next().expect("Arity has been checked"),
+                    )?;
+                )+)?
+                self($($($arg),+)?).into_lox()
+            }
+        }
+    };
+}
+
+impl_native_function!(0);
+impl_native_function!(1; 1 A a);
+impl_native_function!(2; 1 A a, 2 B b);
+impl_native_function!(3; 1 A a, 2 B b, 3 C c);
+impl_native_function!(4; 1 A a, 2 B b, 3 C c, 4 D d);
+impl_native_function!(5; 1 A a, 2 B b, 3 C c, 4 D d, 5 E e);
+impl_native_function!(6; 1 A a, 2 B b, 3 C c, 4 D d, 5 E e, 6 F f);
+impl_native_function!(7; 1 A a, 2 B b, 3 C c, 4 D d, 5 E e, 6 F f, 7 G g);
+impl_native_function!(8; 1 A a, 2 B b, 3 C c, 4 D d, 5 E e, 6 F f, 7 G g, 8 H h);
@@ -0,0 +1,261 @@
+use std::rc::Rc;
+
+use crate::evaluate_expr::binary;
+use crate::expr::Expr;
+use crate::literal_value::LiteralValue;
+use crate::statement::Statement;
+use crate::token_type::TokenType;
+
+/// Simplifies resolved statements without changing what they do: constant expressions are
+/// computed, grouping is removed and branches that can never run are dropped.
+/// Operations that would fail at runtime are kept, so that the error happens in the same place.
+pub fn optimize(statements: Vec<Statement>) -> Vec<Statement> {
+    statements.into_iter().filter_map(statement).collect()
+}
+
+/// Optimizes a statement, or returns None if it does nothing
+fn statement(statement: Statement) -> Option<Statement> {
+    Some(match statement {
+        Statement::Class {
+            name,
+            superclass,
+            methods,
+        } => Statement::Class {
+            name,
+            superclass: superclass.map(expr),
+            methods: methods.into_iter().filter_map(self::statement).collect(),
+        },
+        Statement::Expression { expr: e } => Statement::Expression { expr: expr(e) },
+        Statement::Function { name, params, body } => Statement::Function {
+            name,
+            params,
+            body: Rc::from(optimize(body.to_vec())),
+        },
+        Statement::Return { keyword, expr: e } => Statement::Return {
+            keyword,
+            expr: e.map(expr),
+        },
+        Statement::If {
+            condition,
+            then_branch,
+            else_branch,
+        } => {
+            let condition = expr(condition);
+            match literal(&condition) {
+                Some(value) if value.is_truthy() => self::statement(*then_branch)?,
+                Some(_) => self::statement(*else_branch?)?,
+                None => Statement::If {
+                    condition,
+                    then_branch: Box::new(branch(*then_branch)),
+                    else_branch: else_branch
+                        .and_then(|else_branch| self::statement(*else_branch))
+                        .map(Box::new),
+                },
+            }
+        }
+        Statement::While { condition, block } => {
+            let condition = expr(condition);
+            if literal(&condition).is_some_and(|value| !value.is_truthy()) {
+                return None;
+            }
+            Statement::While {
+                condition,
+                block: Box::new(branch(*block)),
+            }
+        }
+        Statement::Print { expr: e } => Statement::Print { expr: expr(e) },
+        Statement::Block { statements } => Statement::Block {
+            statements: optimize(statements),
+        },
+        Statement::Var { name, initializer } => Statement::Var {
+            name,
+            initializer: initializer.map(expr),
+        },
+    })
+}
+
+/// Optimizes a statement that can't be left out, such as the body of a loop
+fn branch(branch: Statement) -> Statement {
+    statement(branch).unwrap_or(Statement::Block {
+        statements: Vec::new(),
+    })
+}
+
+fn expr(expr: Expr) -> Expr {
+    match expr {
+        Expr::Assign {
+            name,
+            expression,
+            slot,
+        } => Expr::Assign {
+            name,
+            expression: Box::new(self::expr(*expression)),
+            slot,
+        },
+        Expr::Binary {
+            left,
+            operator,
+            right,
+        } => {
+            let left = self::expr(*left);
+            let right = self::expr(*right);
+            let span = left.span().to(right.span());
+            if let (Some(l), Some(r)) = (literal(&left), literal(&right)) {
+                if let Ok(value) = binary(&operator, l.clone(), r.clone(), span) {
+                    return Expr::Literal { value, span };
+                }
+            }
+            Expr::Binary {
+                left: Box::new(left),
+                operator,
+                right: Box::new(right),
+            }
+        }
+        Expr::Call {
+            callee,
+            closing_paren,
+            arguments,
+        } => Expr::Call {
+            callee: Box::new(self::expr(*callee)),
+            closing_paren,
+            arguments: arguments.into_iter().map(self::expr).collect(),
+        },
+        Expr::Logical {
+            left,
+            operator,
+            right,
+        } => {
+            let left = self::expr(*left);
+            let right = self::expr(*right);
+            match literal(&left).map(LiteralValue::is_truthy) {
+                // The value of the left operand decides which operand is the result
+                Some(true) if operator.token_type == TokenType::Or => left,
+                Some(false) if operator.token_type == TokenType::And => left,
+                Some(_) => right,
+                None => Expr::Logical {
+                    left: Box::new(left),
+                    operator,
+                    right: Box::new(right),
+                },
+            }
+        }
+        Expr::Get { object, name } => Expr::Get {
+            object: Box::new(self::expr(*object)),
+            name,
+        },
+        Expr::Grouping { expression, .. } => self::expr(*expression),
+        Expr::Index {
+            object,
+            index,
+            closing_bracket,
+        } => Expr::Index {
+            object: Box::new(self::expr(*object)),
+            index: Box::new(self::expr(*index)),
+            closing_bracket,
+        },
+        Expr::List { elements, span } => Expr::List {
+            elements: elements.into_iter().map(self::expr).collect(),
+            span,
+        },
+        Expr::Set {
+            object,
+            name,
+            value,
+        } => Expr::Set {
+            object: Box::new(self::expr(*object)),
+            name,
+            value: Box::new(self::expr(*value)),
+        },
+        Expr::SetIndex {
+            object,
+            index,
+            closing_bracket,
+            value,
+        } => Expr::SetIndex {
+            object: Box::new(self::expr(*object)),
+            index: Box::new(self::expr(*index)),
+            closing_bracket,
+            value: Box::new(self::expr(*value)),
+        },
+        Expr::Unary { operator, right } => {
+            let right = self::expr(*right);
+            let span = operator.span.to(right.span());
+            match (operator.token_type, literal(&right)) {
+                (TokenType::Bang, Some(value)) => Expr::Literal {
+                    value: LiteralValue::Bool(!value.is_truthy()),
+                    span,
+                },
+                (TokenType::Minus, Some(LiteralValue::Number(number))) => Expr::Literal {
+                    value: LiteralValue::Number(-number),
+                    span,
+                },
+                _ => Expr::Unary {
+                    operator,
+                    right: Box::new(right),
+                },
+            }
+        }
+        expr @ (Expr::Literal { .. }
+        | Expr::Super { .. }
+        | Expr::This { .. }
+        | Expr::Variable { .. }) => expr,
+    }
+}
+
+fn literal(expr: &Expr) -> Option<&LiteralValue> {
+    match expr {
+        Expr::Literal { value, .. } => Some(value),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use crate::error_reporter::ErrorReporter;
+    use crate::parser::Parser;
+    use crate::scanner::TokenScanner;
+    use crate::statement::Statement;
+    use crate::test::{run, sessions};
+
+    #[test]
+    fn optimize() {
+        let source = "
+            fun f(n) {
+                if (1 < 2 and !false) return n * (2 + 3);
+                while (nil) print \"never\";
+                return -1;
+            }
+            print f(2) + 10 / 4;
+            print \"a\" + \"b\" == \"ab\";
+            print false or \"default\";
+            print 1 + 2 / (3 - 3);
+            ";
+        let unoptimized = run(source);
+        for mut lox in sessions() {
+            lox.set_optimize(true);
+            let error = lox.eval(source).unwrap_err();
+            assert_eq!(lox.diagnostics()[0].line, 10);
+            let output = String::from_utf8(lox.into_output()).unwrap() + &error.to_string();
+            assert_eq!(output, unoptimized);
+        }
+    }
+
+    #[test]
+    fn folds_constants() {
+        let source =
+            "print 1+2*3-(2+4)/3; if (!true) print 1; else print x or -(2); while (false) {}";
+        let error = Rc::new(RefCell::new(ErrorReporter::new("<input>", source)));
+        let statements = Parser::new(source.chars().tokens(error.clone()), error).parse();
+        let printed: Vec<_> = super::optimize(statements)
+            .iter()
+            .map(|statement| match statement {
+                Statement::Print { expr } => expr.to_string(),
+                _ => panic!("Only print statements should be left"),
+            })
+            .collect();
+        assert_eq!(printed, ["5", "(Or Identifier x -2)"]);
+    }
+}
@@ -1,6 +1,7 @@
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::rc::Rc;
 
+use crate::diagnostic::Phase;
 use crate::error_reporter;
 use crate::error_reporter::{Error, ErrorReporter};
 use crate::expr::Expr;
@@ -44,6 +45,10 @@ where
 
     fn declaration(&mut self) -> error_reporter::Result<Statement> {
         match self.peek_token_type() {
+            Some(TokenType::Class) => {
+                self.next_token();
+                self.class_declaration()
+            }
             Some(TokenType::Var) => {
                 self.next_token();
                 self.var_declaration()
@@ -56,6 +61,29 @@ where
         }
     }
 
+    fn class_declaration(&mut self) -> error_reporter::Result<Statement> {
+        let name = self.consume(TokenType::Identifier, "Expected class name")?;
+        let superclass = if self.match_token_type(TokenType::Less) {
+            Some(Expr::Variable {
+                name: self.consume(TokenType::Identifier, "Expected superclass name")?,
+                slot: Cell::default(),
+            })
+        } else {
+            None
+        };
+        self.consume(TokenType::LeftBrace, "Expected '{' before class body")?;
+        let mut methods = Vec::new();
+        while !matches!(self.peek_token_type(), None | Some(TokenType::RightBrace)) {
+            methods.push(self.function("method")?);
+        }
+        self.consume(TokenType::RightBrace, "Expected '}' after class body")?;
+        Ok(Statement::Class {
+            name,
+            superclass,
+            methods,
+        })
+    }
+
     fn var_declaration(&mut self) -> error_reporter::Result<Statement> {
         let name = self.consume(TokenType::Identifier, "Expect variable name")?;
         let initializer = if self.match_token_type(TokenType::Equal) {
@@ -93,8 +121,8 @@ where
         )?;
         Ok(Statement::Function {
             name,
-            params,
-            body: self.block()?,
+            params: params.into(),
+            body: self.block()?.into(),
         })
     }
 
@@ -167,9 +195,15 @@ where
         } else {
             Some(self.expression_statement()?)
         };
-        let condition = if self.check_token_type(TokenType::Semicolon) {
+        let condition = if let Some(Token {
+            token_type: TokenType::Semicolon,
+            span,
+            ..
+        }) = self.peek_token()
+        {
             Expr::Literal {
                 value: LiteralValue::Bool(true),
+                span,
             }
         } else {
             self.expression()?
@@ -246,13 +280,39 @@ where
         let lhs = self.logic_or()?;
         if let Some(Token { token_type, .. }) = self.peek_token() {
             if token_type == TokenType::Equal {
-                self.next_token();
+                let equals = self.next_token();
                 let value = self.expression()?;
-                if let Expr::Variable { name } = lhs {
-                    return Ok(Expr::Assign {
-                        name,
-                        expression: Box::new(value),
-                    });
+                match lhs {
+                    Expr::Variable { name, .. } => {
+                        return Ok(Expr::Assign {
+                            name,
+                            expression: Box::new(value),
+                            slot: Cell::default(),
+                        });
+                    }
+                    Expr::Get { object, name } => {
+                        return Ok(Expr::Set {
+                            object,
+                            name,
+                            value: Box::new(value),
+                        });
+                    }
+                    Expr::Index {
+                        object,
+                        index,
+                        closing_bracket,
+                    } => {
+                        return Ok(Expr::SetIndex {
+                            object,
+                            index,
+                            closing_bracket,
+                            value: Box::new(value),
+                        });
+                    }
+                    _ => {
+                        // There is no need to synchronize, as the parser is not confused
+                        self.error(equals, "Invalid assignment target");
+                    }
                 }
             }
         }
@@ -355,24 +415,39 @@ where
     }
 
     fn unary(&mut self) -> error_reporter::Result<Expr> {
-        if let Some(token_type) = self.peek_token_type() {
-            match token_type {
-                TokenType::Bang | TokenType::Minus => {
-                    return Ok(Expr::Unary {
-                        operator: self.next_token().unwrap(),
-                        right: Box::new(self.unary()?),
-                    })
-                }
-                _ => {}
-            }
+        match self.peek_token_type() {
+            Some(TokenType::Bang | TokenType::Minus) => Ok(Expr::Unary {
+                operator: self.next_token().unwrap(),
+                right: Box::new(self.unary()?),
+            }),
+            _ => self.call(),
         }
-        self.call()
     }
 
     fn call(&mut self) -> error_reporter::Result<Expr> {
         let mut expr = self.primary()?;
-        while self.match_token_type(TokenType::LeftParen) {
-            expr = self.finish_call(expr)?;
+        loop {
+            if self.match_token_type(TokenType::LeftParen) {
+                expr = self.finish_call(expr)?;
+            } else if self.match_token_type(TokenType::Dot) {
+                let name =
+                    self.consume(TokenType::Identifier, "Expected property name after '.'")?;
+                expr = Expr::Get {
+                    object: Box::new(expr),
+                    name,
+                };
+            } else if self.match_token_type(TokenType::LeftBracket) {
+                let index = self.expression()?;
+                let closing_bracket =
+                    self.consume(TokenType::RightBracket, "Expected ']' after index")?;
+                expr = Expr::Index {
+                    object: Box::new(expr),
+                    index: Box::new(index),
+                    closing_bracket,
+                };
+            } else {
+                break;
+            }
         }
         Ok(expr)
     }
@@ -403,66 +478,111 @@ where
     }
 
     fn primary(&mut self) -> error_reporter::Result<Expr> {
-        let current = self.tokens.clone();
         match self.next_token() {
             Some(Token {
                 token_type: TokenType::False,
+                span,
                 ..
             }) => Ok(Expr::Literal {
                 value: LiteralValue::Bool(false),
+                span,
             }),
             Some(Token {
                 token_type: TokenType::True,
+                span,
                 ..
             }) => Ok(Expr::Literal {
                 value: LiteralValue::Bool(true),
+                span,
             }),
             Some(Token {
                 token_type: TokenType::Nil,
+                span,
                 ..
             }) => Ok(Expr::Literal {
                 value: LiteralValue::Nil,
+                span,
             }),
-            Some(Token {
-                token_type: TokenType::Number,
-                lexeme,
-                ..
-            }) => Ok(Expr::Literal {
-                value: LiteralValue::Number(lexeme.parse().unwrap()),
-            }),
+            Some(token) if token.token_type == TokenType::Number => match token.lexeme.parse() {
+                Ok(number) => Ok(Expr::Literal {
+                    value: LiteralValue::Number(number),
+                    span: token.span,
+                }),
+                Err(_) => Err(self.error(Some(token), "Invalid number.")),
+            },
             Some(Token {
                 token_type: TokenType::String,
                 lexeme,
+                span,
                 ..
             }) => Ok(Expr::Literal {
-                value: LiteralValue::String(lexeme),
+                value: LiteralValue::String(lexeme.into()),
+                span,
             }),
-            Some(token) if token.token_type == TokenType::Identifier => {
-                Ok(Expr::Variable { name: token })
+            Some(token) if token.token_type == TokenType::This => Ok(Expr::This {
+                keyword: token,
+                slot: Cell::default(),
+            }),
+            Some(token) if token.token_type == TokenType::Super => {
+                self.consume(TokenType::Dot, "Expected '.' after 'super'")?;
+                let method =
+                    self.consume(TokenType::Identifier, "Expected superclass method name")?;
+                Ok(Expr::Super {
+                    keyword: token,
+                    method,
+                    slot: Cell::default(),
+                })
             }
+            Some(token) if token.token_type == TokenType::Identifier => Ok(Expr::Variable {
+                name: token,
+                slot: Cell::default(),
+            }),
             Some(Token {
                 token_type: TokenType::LeftParen,
+                span: left_paren,
                 ..
             }) => {
                 let expression = self.expression()?;
                 let token = self.next_token();
-                if token.as_ref().map(|token| token.token_type) == Some(TokenType::RightParen) {
+                if let Some(Token {
+                    token_type: TokenType::RightParen,
+                    span: right_paren,
+                    ..
+                }) = token
+                {
                     Ok(Expr::Grouping {
                         expression: Box::new(expression),
+                        span: left_paren.to(right_paren),
                     })
                 } else {
                     Err(self.error(token, "Expect ')' after expression."))
                 }
             }
-            None => {
-                let token = current.clone().next();
-                self.tokens = current;
-                Err(self.error(token, "Unexpected end of file"))
+            Some(Token {
+                token_type: TokenType::LeftBracket,
+                span: left_bracket,
+                ..
+            }) => {
+                let mut elements = Vec::new();
+                if !self.check_token_type(TokenType::RightBracket) {
+                    loop {
+                        elements.push(self.expression()?);
+                        if !self.match_token_type(TokenType::Comma) {
+                            break;
+                        }
+                    }
+                }
+                let right_bracket =
+                    self.consume(TokenType::RightBracket, "Expected ']' after list elements")?;
+                Ok(Expr::List {
+                    elements,
+                    span: left_bracket.to(right_bracket.span),
+                })
             }
-            _ => {
-                let token = current.clone().next();
-                self.tokens = current;
-                Err(self.error(token, "Expected expression"))
+            None => Err(self.error(None, "Unexpected end of file")),
+            Some(token) => {
+                self.peeked = Some(token.clone());
+                Err(self.error(Some(token), "Expected expression"))
             }
         }
     }
@@ -516,21 +636,21 @@ where
     fn error(&mut self, token: Option<Token>, message: &str) -> Error {
         self.error_reporter
             .borrow_mut()
-            .error_with_token(token.clone(), message);
+            .error_with_token(Phase::Parse, token.clone(), message);
         Error {
-            token,
+            span: token.as_ref().map(|token| token.span),
+            token: token.map(Box::new),
             message: message.into(),
+            trace: Vec::new(),
         }
     }
 
     fn synchronize(&mut self) {
         loop {
-            if let Some(token) = self.next_token() {
-                if token.token_type == TokenType::Semicolon {
-                    return;
-                }
-                if let Some(next) = self.peek_token_type() {
-                    match next {
+            match self.next_token() {
+                Some(token) if token.token_type == TokenType::Semicolon => return,
+                Some(_) => {
+                    if let Some(
                         TokenType::Class
                         | TokenType::For
                         | TokenType::Fun
@@ -538,12 +658,13 @@ where
                         | TokenType::Print
                         | TokenType::Return
                         | TokenType::Var
-                        | TokenType::While => return,
-                        _ => {}
+                        | TokenType::While,
+                    ) = self.peek_token_type()
+                    {
+                        return;
                     }
                 }
-            } else {
-                return;
+                None => return,
             }
         }
     }
@@ -0,0 +1,180 @@
+use std::fs;
+use std::io::{self, BufRead, Write};
+
+use rlox::{is_incomplete, Lox};
+
+use crate::{report_diagnostics, ErrorFormat};
+
+const HELP: &str = "\
+Enter Lox code to run it. The value of an expression statement is printed.
+Code is not run until all parentheses, braces and strings are closed.
+:env          Show the global variables
+:history      Show what has been entered in this session
+:load <file>  Run a Lox script in this session
+:reset        Start a new session, forgetting all variables
+:quit         Leave the REPL (as does end of input)";
+
+/// An interactive session, which reads from the input and prints to the output.
+pub struct Repl<W: Write> {
+    lox: Lox<W>,
+    error_format: ErrorFormat,
+    /// Show prompts, which is only useful when a user is typing
+    prompt: bool,
+    history: Vec<String>,
+}
+
+impl<W: Write> Repl<W> {
+    /// Runs the code entered in the session, printing the value of expression statements.
+    pub fn new(mut lox: Lox<W>, error_format: ErrorFormat, prompt: bool) -> Self {
+        lox.set_echo_expressions(true);
+        Self {
+            lox,
+            error_format,
+            prompt,
+            history: Vec::new(),
+        }
+    }
+
+    pub fn run(&mut self, mut input: impl BufRead) {
+        let mut source = String::new();
+        loop {
+            if self.prompt {
+                let prompt = if source.is_empty() { "> " } else { "... " };
+                write!(self.lox.output_mut(), "{prompt}").unwrap();
+                self.lox.output_mut().flush().unwrap();
+            }
+            let mut line = String::new();
+            match input.read_line(&mut line) {
+                Ok(0) => return,
+                Ok(_) => {}
+                // The line has been consumed, so the next one can be read
+                Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                    eprintln!("Failed to read input: {e}");
+                    continue;
+                }
+                Err(e) => {
+                    eprintln!("Failed to read input: {e}");
+                    return;
+                }
+            }
+            if source.is_empty() {
+                if line.trim().is_empty() {
+                    continue;
+                }
+                if let Some(command) = line.trim().strip_prefix(':') {
+                    // Added after running, so that :history shows what came before it
+                    let running = self.command(command);
+                    self.history.push(line.trim().to_string());
+                    if !running {
+                        return;
+                    }
+                    continue;
+                }
+            }
+            source.push_str(&line);
+            if !is_incomplete(&source) {
+                self.history.push(source.trim_end().to_string());
+                self.eval("<stdin>", &source);
+                source.clear();
+            }
+        }
+    }
+
+    /// Runs a meta-command, and tells if the REPL should continue
+    fn command(&mut self, command: &str) -> bool {
+        let (name, argument) = command
+            .split_once(char::is_whitespace)
+            .map_or((command, ""), |(name, argument)| (name, argument.trim()));
+        match (name, argument) {
+            ("quit" | "q", "") => return false,
+            ("help" | "h", "") => writeln!(self.lox.output_mut(), "{HELP}").unwrap(),
+            ("env", "") => {
+                for (name, value) in self.lox.globals() {
+                    writeln!(self.lox.output_mut(), "{name} = {value}").unwrap();
+                }
+            }
+            ("history", "") => {
+                for (index, entry) in self.history.iter().enumerate() {
+                    writeln!(self.lox.output_mut(), "{:>4}  {entry}", index + 1).unwrap();
+                }
+            }
+            ("load", file) if !file.is_empty() => match fs::read_to_string(file) {
+                Ok(source) => self.eval(file, &source),
+                Err(e) => eprintln!("Failed to read from file {file}: {e}"),
+            },
+            ("reset", "") => self.lox.reset(),
+            _ => eprintln!("Unknown command :{command}. Enter :help for a list of commands"),
+        }
+        true
+    }
+
+    fn eval(&mut self, file_name: &str, source: &str) {
+        // Errors are reported from the diagnostics
+        let _ = self.lox.eval_named(file_name, source);
+        report_diagnostics(&self.lox, file_name, self.error_format);
+    }
+
+    #[cfg(test)]
+    pub fn into_output(self) -> W {
+        self.lox.into_output()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rlox::{Backend, Lox};
+
+    use super::Repl;
+    use crate::ErrorFormat;
+
+    fn run_repl(input: &str, backend: Backend) -> String {
+        let lox = Lox::with_backend(Vec::new(), backend);
+        let mut repl = Repl::new(lox, ErrorFormat::Human, false);
+        repl.run(input.as_bytes());
+        String::from_utf8(repl.into_output()).unwrap()
+    }
+
+    #[test]
+    fn multi_line_input() {
+        for backend in [Backend::TreeWalker, Backend::Bytecode] {
+            let output = run_repl(
+                "fun add(a, b) {\n  return a +\n    b;\n}\nadd(1, 2);\n",
+                backend,
+            );
+            assert_eq!(output, "3\n");
+        }
+    }
+
+    #[test]
+    fn commands() {
+        let builtins = ["clock", "insert", "len", "pop", "push", "remove", "slice"]
+            .map(|name| format!("{name} = <some function>\n"))
+            .concat();
+        for backend in [Backend::TreeWalker, Backend::Bytecode] {
+            let output = run_repl(
+                "var a = 1;\n:env\n:reset\n:env\n:history\n:quit\nprint a;\n",
+                backend,
+            );
+            assert_eq!(
+                output,
+                format!(
+                    "a = 1\n{builtins}{builtins}   1  var a = 1;\n   2  :env\n   3  :reset\n   4  :env\n"
+                )
+            );
+        }
+    }
+
+    #[test]
+    fn history() {
+        let output = run_repl(":history\n1;\n:history\n", Backend::TreeWalker);
+        assert_eq!(output, "1\n   1  :history\n   2  1;\n");
+    }
+
+    #[test]
+    fn invalid_input_is_skipped() {
+        let lox = Lox::with_output(Vec::new());
+        let mut repl = Repl::new(lox, ErrorFormat::Human, false);
+        repl.run(&b"1;\n\xff;\n2;\n"[..]);
+        assert_eq!(String::from_utf8(repl.into_output()).unwrap(), "1\n2\n");
+    }
+}
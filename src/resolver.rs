@@ -1,28 +1,58 @@
+use crate::diagnostic::{Diagnostic, Phase};
 use crate::error_reporter::ErrorReporter;
-use crate::expr::Expr;
+use crate::expr::{Expr, Slot};
+use crate::span::Span;
 use crate::statement::Statement;
 use crate::token::Token;
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::collections::HashMap;
 use std::rc::Rc;
 
-pub fn resolve(
-    statements: &Vec<Statement>,
-    error_reporter: &Rc<RefCell<ErrorReporter>>,
-) -> ResolveLookup {
+/// Checks the statements for errors that can be found before running them, and gives each
+/// local variable the slot where it will be found at runtime.
+pub fn resolve(statements: &[Statement], error_reporter: &Rc<RefCell<ErrorReporter>>) {
     let mut resolver = Resolver::new(error_reporter.clone());
     resolver.resolve_statements(statements);
-    resolver.into()
 }
 
-struct Resolver {
-    scopes: Vec<HashMap<String, bool>>,
-    error_reporter: Rc<RefCell<ErrorReporter>>,
-    locals: HashMap<Token, usize>,
+#[derive(Clone, Copy, PartialEq)]
+enum FunctionType {
+    Function,
+    Method,
+    Initializer,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum ClassType {
+    Class,
+    Subclass,
 }
 
-pub struct ResolveLookup {
-    locals: HashMap<Token, usize>,
+#[derive(Clone, Copy, PartialEq)]
+enum LocalKind {
+    Variable,
+    Parameter,
+}
+
+struct Local {
+    /// The declaration, or None for `this` and `super`
+    name: Option<Token>,
+    kind: LocalKind,
+    /// The position of the variable in its environment, which is the order of declaration
+    index: usize,
+    /// Set once the initializer has been resolved
+    defined: bool,
+    /// Set when the variable is read. Assigning to it does not count.
+    used: bool,
+}
+
+struct Resolver {
+    scopes: Vec<HashMap<String, Local>>,
+    error_reporter: Rc<RefCell<ErrorReporter>>,
+    /// The functions being resolved, innermost last. Empty at the top level.
+    functions: Vec<FunctionType>,
+    /// The classes being resolved, innermost last
+    classes: Vec<ClassType>,
 }
 
 impl Resolver {
@@ -30,11 +60,17 @@ impl Resolver {
         Self {
             scopes: Vec::new(),
             error_reporter: error,
-            locals: HashMap::new(),
+            functions: Vec::new(),
+            classes: Vec::new(),
         }
     }
 
-    pub fn resolve_statements(&mut self, statements: &Vec<Statement>) {
+    pub fn resolve_statements(&mut self, statements: &[Statement]) {
+        if let Some(index) = statements.iter().position(always_returns) {
+            if let Some(unreachable) = statements.get(index + 1) {
+                self.unreachable_code(&statements[index], unreachable);
+            }
+        }
         for statement in statements {
             self.resolve(statement);
         }
@@ -42,27 +78,90 @@ impl Resolver {
 
     fn resolve(&mut self, statement: &Statement) {
         match statement {
+            Statement::Class {
+                name,
+                superclass,
+                methods,
+            } => {
+                self.classes.push(ClassType::Class);
+                self.declare(name, LocalKind::Variable);
+                self.define(name);
+                if let Some(superclass) = superclass {
+                    if let Expr::Variable {
+                        name: superclass_name,
+                        ..
+                    } = superclass
+                    {
+                        if superclass_name.lexeme == name.lexeme {
+                            (*self.error_reporter).borrow_mut().error_with_token(
+                                Phase::Resolve,
+                                Some(superclass_name.clone()),
+                                "A class can't inherit from itself",
+                            );
+                        }
+                    }
+                    *self.classes.last_mut().unwrap() = ClassType::Subclass;
+                    self.resolve_expr(superclass);
+                    self.begin_scope();
+                    self.define_name("super");
+                }
+                self.begin_scope();
+                self.define_name("this");
+                for method in methods {
+                    if let Statement::Function { name, params, body } = method {
+                        let kind = if name.lexeme == "init" {
+                            FunctionType::Initializer
+                        } else {
+                            FunctionType::Method
+                        };
+                        self.resolve_function(kind, params, body);
+                    }
+                }
+                self.end_scope();
+                if superclass.is_some() {
+                    self.end_scope();
+                }
+                self.classes.pop();
+            }
             Statement::Block { statements } => {
                 self.begin_scope();
                 self.resolve_statements(statements);
                 self.end_scope();
             }
             Statement::Var { name, initializer } => {
-                self.declare(name);
+                self.declare(name, LocalKind::Variable);
                 if let Some(initializer) = initializer {
                     self.resolve_expr(initializer);
                 }
                 self.define(name);
             }
             Statement::Function { name, params, body } => {
-                self.declare(name);
+                self.declare(name, LocalKind::Variable);
                 self.define(name);
-                self.resolve_function(params, body);
+                self.resolve_function(FunctionType::Function, params, body);
             }
             Statement::Expression { expr } | Statement::Print { expr } => {
                 self.resolve_expr(expr);
             }
-            Statement::Return { expr, .. } => {
+            Statement::Return { keyword, expr } => {
+                match self.functions.last() {
+                    None => (*self.error_reporter).borrow_mut().error_with_token(
+                        Phase::Resolve,
+                        Some(keyword.clone()),
+                        "Can't return from top-level code",
+                    ),
+                    Some(FunctionType::Initializer) if expr.is_some() => {
+                        (*self.error_reporter).borrow_mut().report(
+                            Diagnostic::error(
+                                Phase::Resolve,
+                                "Can't return a value from an initializer",
+                            )
+                            .at_token(keyword)
+                            .with_note("an initializer always returns 'this'"),
+                        )
+                    }
+                    Some(_) => {}
+                }
                 if let Some(expr) = expr {
                     self.resolve_expr(expr);
                 }
@@ -85,14 +184,35 @@ impl Resolver {
         }
     }
 
-    fn resolve_function(&mut self, params: &Vec<Token>, body: &Vec<Statement>) {
+    fn resolve_function(&mut self, kind: FunctionType, params: &[Token], body: &[Statement]) {
+        self.functions.push(kind);
         self.begin_scope();
         for param in params {
-            self.declare(param);
+            self.declare(param, LocalKind::Parameter);
             self.define(param);
         }
         self.resolve_statements(body);
         self.end_scope();
+        self.functions.pop();
+    }
+
+    fn unreachable_code(&mut self, returning: &Statement, unreachable: &Statement) {
+        let (Some(returning), Some(unreachable)) =
+            (statement_span(returning), statement_span(unreachable))
+        else {
+            return;
+        };
+        let mut error_reporter = (*self.error_reporter).borrow_mut();
+        let (return_line, _) = error_reporter.line_and_column(returning.start);
+        let diagnostic = error_reporter
+            .at_span(
+                Diagnostic::warning(Phase::Resolve, "Unreachable code"),
+                unreachable,
+            )
+            .with_note(&format!(
+                "the statement on line {return_line} always returns"
+            ));
+        error_reporter.warn("unreachable", diagnostic);
     }
 
     fn begin_scope(&mut self) {
@@ -100,37 +220,98 @@ impl Resolver {
     }
 
     fn end_scope(&mut self) {
-        self.scopes.pop();
+        let Some(scope) = self.scopes.pop() else {
+            return;
+        };
+        let mut unused: Vec<_> = scope
+            .into_values()
+            .filter(|local| !local.used)
+            .filter_map(|local| Some((local.name?, local.kind)))
+            .collect();
+        unused.sort_by_key(|(name, _)| name.span.start);
+        for (name, kind) in unused {
+            let message = match kind {
+                LocalKind::Variable => format!("Unused variable '{}'", name.lexeme),
+                LocalKind::Parameter => format!("Unused parameter '{}'", name.lexeme),
+            };
+            let diagnostic = Diagnostic::warning(Phase::Resolve, &message)
+                .at_token(&name)
+                .with_help("remove it, or add the comment '// lox-allow(unused)'");
+            (*self.error_reporter)
+                .borrow_mut()
+                .warn("unused", diagnostic);
+        }
     }
 
-    fn declare(&mut self, name: &Token) {
+    fn declare(&mut self, name: &Token, kind: LocalKind) {
         if let Some(scope) = self.scopes.last_mut() {
-            scope.insert(name.lexeme.clone(), false);
+            let local = Local {
+                name: Some(name.clone()),
+                kind,
+                index: scope.len(),
+                defined: false,
+                used: false,
+            };
+            if scope.insert(name.lexeme.clone(), local).is_some() {
+                (*self.error_reporter).borrow_mut().report(
+                    Diagnostic::error(
+                        Phase::Resolve,
+                        "Already a variable with this name in this scope",
+                    )
+                    .at_token(name)
+                    .with_help("only global variables can be redeclared"),
+                );
+            }
         }
     }
 
     fn define(&mut self, name: &Token) {
+        if let Some(local) = self
+            .scopes
+            .last_mut()
+            .and_then(|scope| scope.get_mut(&name.lexeme))
+        {
+            local.defined = true;
+        }
+    }
+
+    /// Defines `this` or `super`, which may be left unused
+    fn define_name(&mut self, name: &str) {
         if let Some(scope) = self.scopes.last_mut() {
-            scope.insert(name.lexeme.clone(), true);
+            let local = Local {
+                name: None,
+                kind: LocalKind::Variable,
+                index: scope.len(),
+                defined: true,
+                used: true,
+            };
+            scope.insert(name.to_string(), local);
         }
     }
 
     fn resolve_expr(&mut self, expr: &Expr) {
         match expr {
-            Expr::Variable { name } => {
+            Expr::Variable { name, slot } => {
                 if let Some(scope) = self.scopes.last() {
-                    if scope.get(&name.lexeme) == Some(&false) {
-                        (*self.error_reporter).borrow_mut().error_with_token(
-                            Some(name.clone()),
-                            "Can't read local variable in its own initializer",
+                    if scope.get(&name.lexeme).is_some_and(|local| !local.defined) {
+                        (*self.error_reporter).borrow_mut().report(
+                            Diagnostic::error(Phase::Resolve, "Can't read local variable in its own initializer")
+                                .at_token(name)
+                                .with_note("a variable is not defined until its initializer has been evaluated"),
                         );
                     }
-                    self.resolve_local(name);
+                    if let Some(local) = self.resolve_local(name, slot) {
+                        local.used = true;
+                    }
                 }
             }
-            Expr::Assign { name, expression } => {
+            Expr::Assign {
+                name,
+                expression,
+                slot,
+            } => {
                 self.resolve_expr(expression);
-                self.resolve_local(name);
+                self.resolve_local(name, slot);
             }
             Expr::Binary { left, right, .. } | Expr::Logical { left, right, .. } => {
                 self.resolve_expr(left);
@@ -144,7 +325,62 @@ impl Resolver {
                     self.resolve_expr(arg);
                 }
             }
-            Expr::Grouping { expression }
+            Expr::Get { object, .. } => {
+                self.resolve_expr(object);
+            }
+            Expr::Set { object, value, .. } => {
+                self.resolve_expr(value);
+                self.resolve_expr(object);
+            }
+            Expr::Index { object, index, .. } => {
+                self.resolve_expr(object);
+                self.resolve_expr(index);
+            }
+            Expr::SetIndex {
+                object,
+                index,
+                value,
+                ..
+            } => {
+                self.resolve_expr(object);
+                self.resolve_expr(index);
+                self.resolve_expr(value);
+            }
+            Expr::List { elements, .. } => {
+                for element in elements {
+                    self.resolve_expr(element);
+                }
+            }
+            Expr::Super { keyword, slot, .. } => match self.classes.last() {
+                None => (*self.error_reporter).borrow_mut().error_with_token(
+                    Phase::Resolve,
+                    Some(keyword.clone()),
+                    "Can't use 'super' outside of a class",
+                ),
+                Some(ClassType::Class) => (*self.error_reporter).borrow_mut().report(
+                    Diagnostic::error(
+                        Phase::Resolve,
+                        "Can't use 'super' in a class with no superclass",
+                    )
+                    .at_token(keyword)
+                    .with_help("declare a superclass with 'class Name < Superclass'"),
+                ),
+                Some(ClassType::Subclass) => {
+                    self.resolve_local(keyword, slot);
+                }
+            },
+            Expr::This { keyword, slot } => {
+                if self.classes.is_empty() {
+                    (*self.error_reporter).borrow_mut().error_with_token(
+                        Phase::Resolve,
+                        Some(keyword.clone()),
+                        "Can't use 'this' outside of a class",
+                    );
+                } else {
+                    self.resolve_local(keyword, slot);
+                }
+            }
+            Expr::Grouping { expression, .. }
             | Expr::Unary {
                 right: expression, ..
             } => {
@@ -154,29 +390,47 @@ impl Resolver {
         }
     }
 
-    fn resolve_local(&mut self, name: &Token) {
-        for i in (0..self.scopes.len()).rev() {
-            if self.scopes[i].contains_key(&name.lexeme) {
-                self.store_resolve_result(name, self.scopes.len() - 1 - i);
-            }
-        }
-    }
-
-    fn store_resolve_result(&mut self, name: &Token, depth: usize) {
-        self.locals.insert(name.clone(), depth);
+    /// Finds a local variable and stores its slot. Variables that are not found are global.
+    fn resolve_local(&mut self, name: &Token, slot: &Cell<Option<Slot>>) -> Option<&mut Local> {
+        let scope = self
+            .scopes
+            .iter()
+            .rposition(|scope| scope.contains_key(&name.lexeme))?;
+        let depth = self.scopes.len() - 1 - scope;
+        let local = self.scopes[scope].get_mut(&name.lexeme)?;
+        slot.set(Some(Slot {
+            depth,
+            index: local.index,
+        }));
+        Some(local)
     }
 }
 
-impl From<Resolver> for ResolveLookup {
-    fn from(value: Resolver) -> Self {
-        Self {
-            locals: value.locals,
-        }
+/// Tells if executing the statement always ends with a return
+fn always_returns(statement: &Statement) -> bool {
+    match statement {
+        Statement::Return { .. } => true,
+        Statement::Block { statements } => statements.iter().any(always_returns),
+        Statement::If {
+            then_branch,
+            else_branch: Some(else_branch),
+            ..
+        } => always_returns(then_branch) && always_returns(else_branch),
+        _ => false,
     }
 }
 
-impl ResolveLookup {
-    pub fn get(&self, name: &Token) -> Option<&usize> {
-        self.locals.get(name)
+/// The start of the statement, used to point at it in warnings
+fn statement_span(statement: &Statement) -> Option<Span> {
+    match statement {
+        Statement::Class { name, .. }
+        | Statement::Function { name, .. }
+        | Statement::Var { name, .. } => Some(name.span),
+        Statement::Return { keyword, .. } => Some(keyword.span),
+        Statement::Expression { expr } | Statement::Print { expr } => Some(expr.span()),
+        Statement::If { condition, .. } | Statement::While { condition, .. } => {
+            Some(condition.span())
+        }
+        Statement::Block { statements } => statements.first().and_then(statement_span),
     }
 }
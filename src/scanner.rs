@@ -1,5 +1,8 @@
+use crate::diagnostic::{Diagnostic, Phase};
+use crate::error_reporter::ErrorReporter;
+use crate::span::Span;
+use crate::token::Token;
 use crate::token_type::TokenType;
-use crate::{ErrorReporter, Token};
 use std::cell::RefCell;
 use std::rc::Rc;
 
@@ -19,14 +22,44 @@ where
     }
 }
 
+/// Iterates over the characters of the source code while keeping track of the position.
+/// Backtracking by restoring a clone also restores the position.
+#[derive(Clone)]
+struct Source<I> {
+    chars: I,
+    offset: usize,
+    line: usize,
+    column: usize,
+}
+
+impl<I> Iterator for Source<I>
+where
+    I: Iterator<Item = char>,
+{
+    type Item = char;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let c = self.chars.next()?;
+        self.offset += c.len_utf8();
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(c)
+    }
+}
+
 #[derive(Clone)]
 pub struct Scanner<I>
 where
     I: Iterator<Item = char> + Clone,
 {
-    source: I,
+    source: Source<I>,
     error_reporter: Rc<RefCell<ErrorReporter>>,
-    line: usize,
+    /// Byte offset, line and column of the start of the current token
+    start: (usize, usize, usize),
     count: usize,
 }
 
@@ -36,9 +69,14 @@ where
 {
     pub fn new(source: I, error: Rc<RefCell<ErrorReporter>>) -> Self {
         Self {
-            source,
+            source: Source {
+                chars: source,
+                offset: 0,
+                line: 1,
+                column: 1,
+            },
             error_reporter: error,
-            line: 1,
+            start: (0, 1, 1),
             count: 0,
         }
     }
@@ -64,22 +102,31 @@ where
     }
 
     fn token(&mut self, token_type: TokenType) -> Token {
-        self.count += 1;
-        Token::new(token_type, String::new(), self.line, self.count)
+        self.token_with_lexeme(token_type, symbol(token_type).to_string())
     }
 
     fn token_with_lexeme(&mut self, token_type: TokenType, lexeme: String) -> Token {
         self.count += 1;
-        Token::new(token_type, lexeme, self.line, self.count)
+        let (start, line, column) = self.start;
+        Token {
+            token_type,
+            lexeme,
+            line,
+            column,
+            span: Span::new(start, self.source.offset),
+            count: self.count,
+        }
     }
 
-    fn consume_line(&mut self) {
-        for c in self.source.by_ref() {
-            if c == '\n' {
-                self.line += 1;
-                break;
-            }
-        }
+    /// Places the diagnostic at the part of the source code scanned for the current token
+    fn at_start(&self, diagnostic: Diagnostic) -> Diagnostic {
+        let (start, line, column) = self.start;
+        diagnostic.at(line, column, Span::new(start, self.source.offset))
+    }
+
+    /// Consumes the rest of the line and returns it, without the line break
+    fn consume_line(&mut self) -> String {
+        self.source.by_ref().take_while(|c| *c != '\n').collect()
     }
 
     fn string_literal(&mut self) -> Option<String> {
@@ -87,18 +134,16 @@ where
         loop {
             if let Some(c) = self.source.next() {
                 match c {
-                    '\n' => {
-                        self.line += 1;
-                    }
                     '"' => return Some(literal),
                     _ => {
                         literal.push(c);
                     }
                 }
             } else {
-                self.error_reporter
-                    .borrow_mut()
-                    .error(self.line, "Unterminated string");
+                let diagnostic = self
+                    .at_start(Diagnostic::error(Phase::Scan, "Unterminated string"))
+                    .with_help("strings end with a '\"'");
+                self.error_reporter.borrow_mut().report(diagnostic);
                 return None;
             }
         }
@@ -106,11 +151,15 @@ where
 
     fn number(&mut self, c: char) -> Option<String> {
         let mut number: String = c.into();
+        let mut seen_dot = false;
         loop {
             let previous = self.source.clone();
             match self.source.next() {
-                Some(c) if c.is_numeric() => number.push(c),
-                Some('.') if self.peek().is_numeric() => number.push(c),
+                Some(c) if c.is_ascii_digit() => number.push(c),
+                Some('.') if !seen_dot && self.peek().is_ascii_digit() => {
+                    seen_dot = true;
+                    number.push('.');
+                }
                 Some(_) => {
                     self.source = previous;
                     return Some(number);
@@ -141,7 +190,7 @@ where
 
     fn reserved_word_token(&mut self, identifier: &str) -> Option<Token> {
         if let Some(token_type) = reserved_word_token_type(identifier) {
-            return Some(self.token(token_type));
+            return Some(self.token_with_lexeme(token_type, identifier.to_string()));
         }
         None
     }
@@ -155,12 +204,15 @@ where
 
     fn next(&mut self) -> Option<Self::Item> {
         loop {
+            self.start = (self.source.offset, self.source.line, self.source.column);
             match self.source.next() {
                 // Single character tokens:
                 Some('(') => return Some(self.token(TokenType::LeftParen)),
                 Some(')') => return Some(self.token(TokenType::RightParen)),
                 Some('{') => return Some(self.token(TokenType::LeftBrace)),
                 Some('}') => return Some(self.token(TokenType::RightBrace)),
+                Some('[') => return Some(self.token(TokenType::LeftBracket)),
+                Some(']') => return Some(self.token(TokenType::RightBracket)),
                 Some(',') => return Some(self.token(TokenType::Comma)),
                 Some('.') => return Some(self.token(TokenType::Dot)),
                 Some('-') => return Some(self.token(TokenType::Minus)),
@@ -201,17 +253,16 @@ where
                 // Slash and comments
                 Some('/') => {
                     if self.match_next('/') {
-                        self.consume_line();
+                        let comment = format!("//{}", self.consume_line());
+                        let (_, line, _) = self.start;
+                        self.error_reporter.borrow_mut().comment(line, comment);
                     } else {
                         return Some(self.token(TokenType::Slash));
                     }
                 }
 
                 // Whitespace etc:
-                Some(' ' | '\r' | '\t') => {}
-                Some('\n') => {
-                    self.line += 1;
-                }
+                Some(' ' | '\r' | '\t' | '\n') => {}
 
                 // Strings
                 Some('"') => {
@@ -221,7 +272,7 @@ where
                 }
 
                 // Numbers
-                Some(c) if c.is_numeric() => {
+                Some(c) if c.is_ascii_digit() => {
                     if let Some(number) = self.number(c) {
                         return Some(self.token_with_lexeme(TokenType::Number, number));
                     }
@@ -238,17 +289,46 @@ where
 
                 // Identifiers and reserved words
                 None => return None,
-                _ => self
-                    .error_reporter
-                    .borrow_mut()
-                    .error(self.line, "Unexpected character"),
+                _ => {
+                    let diagnostic =
+                        self.at_start(Diagnostic::error(Phase::Scan, "Unexpected character"));
+                    self.error_reporter.borrow_mut().report(diagnostic);
+                }
             }
         }
     }
 }
 
 fn is_identifier_char(c: char) -> bool {
-    c.is_numeric() || c.is_ascii_alphabetic()
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+/// The lexeme of a token made of one or two characters
+fn symbol(token_type: TokenType) -> &'static str {
+    match token_type {
+        TokenType::LeftParen => "(",
+        TokenType::RightParen => ")",
+        TokenType::LeftBrace => "{",
+        TokenType::RightBrace => "}",
+        TokenType::LeftBracket => "[",
+        TokenType::RightBracket => "]",
+        TokenType::Comma => ",",
+        TokenType::Dot => ".",
+        TokenType::Minus => "-",
+        TokenType::Plus => "+",
+        TokenType::Semicolon => ";",
+        TokenType::Slash => "/",
+        TokenType::Star => "*",
+        TokenType::Bang => "!",
+        TokenType::BangEqual => "!=",
+        TokenType::Equal => "=",
+        TokenType::EqualEqual => "==",
+        TokenType::Greater => ">",
+        TokenType::GreaterEqual => ">=",
+        TokenType::Less => "<",
+        TokenType::LessEqual => "<=",
+        _ => panic!("{token_type} is not a symbol"),
+    }
 }
 
 fn reserved_word_token_type(identifier: &str) -> Option<TokenType> {
@@ -272,3 +352,70 @@ fn reserved_word_token_type(identifier: &str) -> Option<TokenType> {
         _ => None,
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use crate::error_reporter::ErrorReporter;
+    use crate::scanner::TokenScanner;
+    use crate::span::Span;
+    use crate::token_type::TokenType;
+
+    #[test]
+    fn token_positions() {
+        let error = Rc::new(RefCell::new(ErrorReporter::default()));
+        let tokens: Vec<_> = "var s = \"é\";\n  print s;"
+            .chars()
+            .tokens(error)
+            .map(|token| (token.line, token.column, token.span))
+            .collect();
+        assert_eq!(
+            tokens,
+            vec![
+                (1, 1, Span::new(0, 3)),
+                (1, 5, Span::new(4, 5)),
+                (1, 7, Span::new(6, 7)),
+                (1, 9, Span::new(8, 12)),
+                (1, 12, Span::new(12, 13)),
+                (2, 3, Span::new(16, 21)),
+                (2, 9, Span::new(22, 23)),
+                (2, 10, Span::new(23, 24)),
+            ]
+        );
+    }
+
+    #[test]
+    fn symbol_lexemes() {
+        let error = Rc::new(RefCell::new(ErrorReporter::default()));
+        let lexemes: Vec<_> = "( ) { } [ ] , . - + ; / * ! != = == > >= < <="
+            .chars()
+            .tokens(error)
+            .map(|token| token.lexeme)
+            .collect();
+        assert_eq!(
+            lexemes.join(" "),
+            "( ) { } [ ] , . - + ; / * ! != = == > >= < <="
+        );
+    }
+
+    #[test]
+    fn number_with_two_dots() {
+        let error = Rc::new(RefCell::new(ErrorReporter::default()));
+        let tokens: Vec<_> = "1.2.3"
+            .chars()
+            .tokens(error.clone())
+            .map(|token| (token.token_type, token.lexeme))
+            .collect();
+        assert_eq!(
+            tokens,
+            [
+                (TokenType::Number, "1.2".to_string()),
+                (TokenType::Dot, ".".to_string()),
+                (TokenType::Number, "3".to_string()),
+            ]
+        );
+        assert!(!error.borrow().has_error());
+    }
+}
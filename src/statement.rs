@@ -1,15 +1,23 @@
+use std::rc::Rc;
+
 use crate::expr::Expr;
 use crate::token::Token;
 
 #[derive(Clone)]
 pub enum Statement {
+    Class {
+        name: Token,
+        superclass: Option<Expr>,
+        methods: Vec<Statement>,
+    },
     Expression {
         expr: Expr,
     },
     Function {
         name: Token,
-        params: Vec<Token>,
-        body: Vec<Statement>,
+        /// Shared with the functions created when the declaration is executed
+        params: Rc<[Token]>,
+        body: Rc<[Statement]>,
     },
     Return {
         keyword: Token,
@@ -1,3 +1,4 @@
+use crate::span::Span;
 use crate::token_type::TokenType;
 use std::fmt::{Display, Formatter};
 
@@ -6,6 +7,9 @@ pub struct Token {
     pub token_type: TokenType,
     pub lexeme: String,
     pub line: usize,
+    /// The column of the first character of the token, counting characters from 1
+    pub column: usize,
+    pub span: Span,
     pub count: usize,
 }
 
@@ -21,6 +25,8 @@ impl Token {
             token_type,
             lexeme,
             line,
+            column: 0,
+            span: Span::default(),
             count,
         }
     }
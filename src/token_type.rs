@@ -7,6 +7,8 @@ pub enum TokenType {
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
     Comma,
     Dot,
     Minus,
@@ -56,6 +58,8 @@ impl Display for TokenType {
             Self::RightParen => write!(f, "RightParen"),
             Self::LeftBrace => write!(f, "LeftBrace"),
             Self::RightBrace => write!(f, "RightBrace"),
+            Self::LeftBracket => write!(f, "LeftBracket"),
+            Self::RightBracket => write!(f, "RightBracket"),
             Self::Comma => write!(f, "Comma"),
             Self::Dot => write!(f, "Dot"),
             Self::Minus => write!(f, "Minus"),
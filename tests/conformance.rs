@@ -0,0 +1,228 @@
+//! Runs Lox scripts that are annotated like the test suite of the book
+//! (<https://github.com/munificent/craftinginterpreters/tree/master/test>), with both backends:
+//!
+//! - `// expect: <output>` is a line printed by the script.
+//! - `// expect runtime error: <message>` is an error that stops the script on that line.
+//! - `// [line N] Error at '<lexeme>': <message>` is an error that is found before the script
+//!   runs. The line defaults to the line of the comment. `[java line N]` only applies to the
+//!   tree-walker and `[c line N]` only to the bytecode VM, like jlox and clox in the book.
+//!
+//! All the scripts in `tests/lox` must pass. To see which directories of another suite pass,
+//! such as a copy of the upstream one, run
+//! `LOX_TEST_SUITE=<dir> cargo test --test conformance -- --ignored --nocapture`.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use rlox::{Backend, Diagnostic, Lox, Phase, Severity};
+
+const BACKENDS: [Backend; 2] = [Backend::TreeWalker, Backend::Bytecode];
+
+#[derive(Debug, Default, PartialEq)]
+struct Outcome {
+    output: Vec<String>,
+    /// Errors found before running, in the book's format: `[line 1] Error at 'x': message`
+    errors: Vec<String>,
+    /// The line and message of an error that stopped the script
+    runtime_error: Option<(usize, String)>,
+}
+
+fn expected_outcome(source: &str, backend: Backend) -> Outcome {
+    let mut expected = Outcome::default();
+    for (index, line) in source.lines().enumerate() {
+        let Some(start) = line.find("// ") else {
+            continue;
+        };
+        let comment = &line[start + 3..];
+        if let Some(output) = comment.strip_prefix("expect: ") {
+            expected.output.push(output.to_string());
+        } else if let Some(message) = comment.strip_prefix("expect runtime error: ") {
+            expected.runtime_error = Some((index + 1, message.to_string()));
+        } else if let Some(error) = expected_error(comment, index + 1, backend) {
+            expected.errors.push(error);
+        }
+    }
+    expected.errors.sort();
+    expected
+}
+
+fn expected_error(comment: &str, line: usize, backend: Backend) -> Option<String> {
+    let (line, error) = if let Some(rest) = comment.strip_prefix('[') {
+        let (location, error) = rest.split_once("] ")?;
+        let (language, line) = location.rsplit_once("line ")?;
+        match (language.trim(), backend) {
+            ("", _) | ("java", Backend::TreeWalker) | ("c", Backend::Bytecode) => {}
+            _ => return None,
+        }
+        (line.parse().ok()?, error)
+    } else {
+        (line, comment)
+    };
+    error
+        .starts_with("Error")
+        .then(|| format!("[line {line}] {error}"))
+}
+
+fn actual_outcome(path: &Path, source: &str, backend: Backend) -> Outcome {
+    let mut lox = Lox::with_backend(Vec::new(), backend);
+    // Failures are found in the diagnostics
+    let _ = lox.eval_named(&path.display().to_string(), source);
+    let mut errors: Vec<_> = lox
+        .diagnostics()
+        .iter()
+        .filter(|d| d.severity == Severity::Error && d.phase != Phase::Runtime)
+        .map(book_format)
+        .collect();
+    errors.sort();
+    let runtime_error = lox
+        .diagnostics()
+        .iter()
+        .find(|d| d.phase == Phase::Runtime)
+        .map(|d| (d.line, d.message.clone()));
+    let output = String::from_utf8(lox.into_output()).unwrap();
+    Outcome {
+        output: output.lines().map(str::to_string).collect(),
+        errors,
+        runtime_error,
+    }
+}
+
+fn book_format(diagnostic: &Diagnostic) -> String {
+    let location = match (&diagnostic.token, diagnostic.phase) {
+        (Some(token), _) => format!(" at '{}'", token.lexeme),
+        (None, Phase::Parse) => " at end".to_string(),
+        (None, _) => String::new(),
+    };
+    format!(
+        "[line {}] Error{location}: {}",
+        diagnostic.line, diagnostic.message
+    )
+}
+
+/// Describes how the outcome differs from the expected one
+fn mismatches(expected: &Outcome, actual: &Outcome) -> Vec<String> {
+    let mut mismatches = Vec::new();
+    let lines = expected.output.len().max(actual.output.len());
+    for (index, (expected, actual)) in (0..lines)
+        .map(|i| (expected.output.get(i), actual.output.get(i)))
+        .enumerate()
+    {
+        match (expected, actual) {
+            (Some(expected), Some(actual)) if expected == actual => {}
+            (Some(expected), Some(actual)) => mismatches.push(format!(
+                "output line {} is '{actual}', expected '{expected}'",
+                index + 1
+            )),
+            (Some(expected), None) => mismatches.push(format!("missing output '{expected}'")),
+            (None, Some(actual)) => mismatches.push(format!("unexpected output '{actual}'")),
+            (None, None) => unreachable!(),
+        }
+    }
+    for error in &expected.errors {
+        if !actual.errors.contains(error) {
+            mismatches.push(format!("missing error {error}"));
+        }
+    }
+    for error in &actual.errors {
+        if !expected.errors.contains(error) {
+            mismatches.push(format!("unexpected error {error}"));
+        }
+    }
+    if expected.runtime_error != actual.runtime_error {
+        let describe = |error: &Option<(usize, String)>| match error {
+            Some((line, message)) => format!("'{message}' on line {line}"),
+            None => "none".to_string(),
+        };
+        mismatches.push(format!(
+            "runtime error is {}, expected {}",
+            describe(&actual.runtime_error),
+            describe(&expected.runtime_error)
+        ));
+    }
+    mismatches
+}
+
+fn lox_files(dir: &Path, files: &mut Vec<PathBuf>) {
+    let mut entries: Vec<_> = fs::read_dir(dir)
+        .unwrap_or_else(|e| panic!("Can't read {}: {e}", dir.display()))
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    entries.sort();
+    for path in entries {
+        if path.is_dir() {
+            lox_files(&path, files);
+        } else if path.extension().is_some_and(|extension| extension == "lox") {
+            files.push(path);
+        }
+    }
+}
+
+/// The results of running a suite: the failures, and the number of passed and run scripts
+/// for each directory and backend
+#[derive(Default)]
+struct Results {
+    failures: Vec<String>,
+    directories: BTreeMap<(String, String), (usize, usize)>,
+}
+
+fn run_suite(dir: &Path) -> Results {
+    let mut files = Vec::new();
+    lox_files(dir, &mut files);
+    let mut results = Results::default();
+    for path in files {
+        let source = fs::read_to_string(&path).unwrap();
+        let directory = path
+            .parent()
+            .unwrap()
+            .strip_prefix(dir)
+            .unwrap()
+            .display()
+            .to_string();
+        for backend in BACKENDS {
+            let expected = expected_outcome(&source, backend);
+            let actual = actual_outcome(&path, &source, backend);
+            let count = results
+                .directories
+                .entry((directory.clone(), format!("{backend:?}")))
+                .or_default();
+            count.1 += 1;
+            let mismatches = mismatches(&expected, &actual);
+            if mismatches.is_empty() {
+                count.0 += 1;
+            } else {
+                results.failures.push(format!(
+                    "{} ({backend:?}):\n    {}",
+                    path.display(),
+                    mismatches.join("\n    ")
+                ));
+            }
+        }
+    }
+    results
+}
+
+#[test]
+fn lox_test_suite() {
+    let results = run_suite(&Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/lox"));
+    assert!(!results.directories.is_empty());
+    assert!(
+        results.failures.is_empty(),
+        "{}",
+        results.failures.join("\n")
+    );
+}
+
+#[test]
+#[ignore = "needs LOX_TEST_SUITE to point at a test suite"]
+fn external_test_suite() {
+    let dir = std::env::var("LOX_TEST_SUITE").expect("LOX_TEST_SUITE is not set");
+    let results = run_suite(Path::new(&dir));
+    for failure in &results.failures {
+        println!("{failure}");
+    }
+    println!();
+    for ((directory, backend), (passed, total)) in &results.directories {
+        println!("{directory:<24} {backend:<12} {passed:>4} / {total}");
+    }
+}